@@ -0,0 +1,46 @@
+use crate::engine::{Context, VoicersData};
+use crate::error::VoicersError;
+use poise::serenity_prelude as serenity;
+use tracing::debug;
+
+// Checks shared between commands
+
+// Admins, or anyone listed under [moderation] by role or user ID
+pub async fn author_is_moderator<D: VoicersData>(ctx: Context<'_, D>) -> bool {
+    let moderation = &ctx.data().config().moderation;
+    let author_id = ctx.author().id.to_string();
+    if moderation.moderator_users.contains(&author_id) {
+        return true;
+    }
+
+    let Some(member) = ctx.author_member().await else {
+        return false;
+    };
+    let is_admin = member
+        .permissions
+        .map(serenity::Permissions::administrator)
+        .unwrap_or_default();
+    let is_moderator = member
+        .roles
+        .iter()
+        .any(|role_id| moderation.moderator_roles.contains(&role_id.to_string()));
+    debug!("is_admin: {} is_moderator: {}", is_admin, is_moderator);
+    is_admin || is_moderator
+}
+
+// The guild the command ran in, commands that work on channels have nothing to do in DMs
+pub fn guild_only<D: VoicersData>(ctx: Context<'_, D>) -> Result<serenity::GuildId, VoicersError> {
+    ctx.guild_id().ok_or_else(|| {
+        VoicersError::Validation("This command must be used in a server".to_string())
+    })
+}
+
+// For check = "is_moderator" on moderator only commands
+pub async fn is_moderator<D: VoicersData>(ctx: Context<'_, D>) -> Result<bool, VoicersError> {
+    if !author_is_moderator(ctx).await {
+        return Err(VoicersError::Permission(
+            "Only moderators can use this command".to_string(),
+        ));
+    }
+    Ok(true)
+}
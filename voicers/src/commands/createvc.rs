@@ -1,4 +1,10 @@
-use crate::{config, discord};
+use crate::commands::checks::guild_only;
+use crate::config::{Feature, VideoQuality};
+use crate::discord_api::{self, VoiceSettings};
+use crate::engine::{Context, VoicersData};
+use crate::error::VoicersError;
+use crate::lifecycle::{self, CreateRequest, VcType};
+use crate::{presets, storage};
 use tracing::error;
 /*
 use serenity::all::{
@@ -8,33 +14,71 @@ use serenity::all::{
 */
 
 use poise::serenity_prelude as serenity;
-use std::time::{SystemTime, UNIX_EPOCH};
+use serenity::Mentionable;
 use tracing::{debug, info};
 
-async fn autocomplete_type(ctx: discord::Context<'_>, _args: &str) -> Vec<String> {
-    vec!["Private".to_string(), "Public".to_string()]
+async fn autocomplete_type<D: VoicersData>(ctx: Context<'_, D>, _args: &str) -> Vec<String> {
+    let config = ctx.data().config();
+    let mut types = Vec::new();
+    if config.is_feature_enabled(Feature::CreateVcPrivate) {
+        types.push("Private".to_string());
+    }
+    if config.is_feature_enabled(Feature::CreateVcPublic) {
+        types.push("Public".to_string());
+    }
+    types
+}
+
+// https://discord.com/developers/docs/resources/voice#list-voice-regions
+const RTC_REGIONS: &[&str] = &[
+    "brazil",
+    "hongkong",
+    "india",
+    "japan",
+    "rotterdam",
+    "russia",
+    "singapore",
+    "south-korea",
+    "southafrica",
+    "sydney",
+    "us-central",
+    "us-east",
+    "us-south",
+    "us-west",
+];
+
+async fn autocomplete_region<D: VoicersData>(
+    _ctx: Context<'_, D>,
+    partial: &str,
+) -> Vec<&'static str> {
+    RTC_REGIONS
+        .iter()
+        .copied()
+        .filter(|region| region.starts_with(&partial.to_lowercase()))
+        .collect()
+}
+
+async fn autocomplete_preset<D: VoicersData>(ctx: Context<'_, D>, partial: &str) -> Vec<String> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Vec::new();
+    };
+    storage::preset_names(
+        ctx.data().pool(),
+        guild_id.get() as i64,
+        ctx.author().id.get() as i64,
+    )
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .filter(|name| name.to_lowercase().starts_with(&partial.to_lowercase()))
+    .collect()
 }
 
 // Check if the user has the admin role or another role set by the server owner
 // If they do then they can create a VC
 // if they dont then respond with the message (vc_no_permission) set in the config
-async fn is_admin_or_approved(ctx: discord::Context<'_>) -> Result<bool, discord::Error> {
-    let vcmisc_config = config::get_vcmisc_config();
-
-    let approved_role_ids = &config::get_config().misc.vc_mandatory_roles;
-
-    // Convert the string role IDs to RoleId objects, ignoring invalid entries
-    let approved_role_ids: Vec<serenity::RoleId> = approved_role_ids
-        .iter()
-        .filter_map(|id_str| {
-            if id_str.is_empty() {
-                None // Skip empty strings
-            } else {
-                // Parse the string as u64, and then convert to RoleId
-                id_str.parse::<u64>().ok().map(serenity::RoleId::from)
-            }
-        })
-        .collect();
+async fn is_admin_or_approved<D: VoicersData>(ctx: Context<'_, D>) -> Result<bool, VoicersError> {
+    let vcmisc_config = &ctx.data().config().misc;
 
     // This does black magic BUT the basic gist is
     // 1. Get the member object of the user who sent the message
@@ -42,60 +86,79 @@ async fn is_admin_or_approved(ctx: discord::Context<'_>) -> Result<bool, discord
     // 2b. Check if the member has any of the roles set in the config
     // 3. If the member has the admin role or any of the roles set in the config then return true
     // 4. If the member does not have the admin role or any of the roles set in the config then return false
-    // 4a. Respond with the config message (vc_no_permission)
+    // 4a. Return a Permission error with the config message (vc_no_permission), on_error replies with it
     match ctx.author_member().await {
         Some(member) => {
             let is_admin = member
                 .permissions
                 .map(serenity::Permissions::administrator)
                 .unwrap_or_default();
-            let is_approved = member
-                .roles
-                .iter()
-                .any(|role_id| approved_role_ids.contains(role_id));
+            let is_approved =
+                lifecycle::has_mandatory_role(&member.roles, &vcmisc_config.vc_mandatory_roles);
             debug!(
                 "is_admin: {} is_approved: {} responseMessage: {}",
                 is_admin, is_approved, vcmisc_config.vc_no_permission
             );
+            // on_error replies with the message
             if !is_admin && !is_approved {
-                ctx.send(
-                    poise::CreateReply::default()
-                        .content(format!("{}", vcmisc_config.vc_no_permission))
-                        .ephemeral(true),
-                )
-                .await?;
+                let message = if vcmisc_config.vc_no_permission.is_empty() {
+                    "You are not allowed to create voice channels".to_string()
+                } else {
+                    vcmisc_config.vc_no_permission.clone()
+                };
+                return Err(VoicersError::Permission(message));
             }
-            Ok(is_admin || is_approved)
+            Ok(true)
         }
         None => Ok(false),
     }
 }
 
 /// Create a voice channel for the user to join
+#[allow(clippy::too_many_arguments)]
 #[poise::command(
     rename = "createvc",
     track_edits,
     slash_command,
     check = "is_admin_or_approved"
 )]
-pub async fn entrance(
-    ctx: discord::Context<'_>,
-    #[description = "Private or Public"]
+pub async fn entrance<D: VoicersData>(
+    ctx: Context<'_, D>,
+    #[description = "Private or Public, can be left out when using a preset"]
     #[autocomplete = "autocomplete_type"]
-    vctype: String,
+    vctype: Option<String>,
+    #[description = "Recreate a voice channel saved with /vc preset save"]
+    #[autocomplete = "autocomplete_preset"]
+    preset: Option<String>,
     #[description = "Name of the voice channel"] vcname: Option<String>,
     #[description = "Ping a user or role to add them to private VC"] pingadd1: Option<String>,
     #[description = "Ping a user or role to add them to private VC"] pingadd2: Option<String>,
     #[description = "Ping a user or role to add them to private VC"] pingadd3: Option<String>,
     #[description = "Ping a user or role to add them to private VC"] pingadd4: Option<String>,
     #[description = "Ping a user or role to add them to private VC"] pingadd5: Option<String>,
-) -> Result<(), discord::Error> {
+    #[description = "How many members fit in the VC, 0 for no limit"]
+    #[min = 0]
+    #[max = 99]
+    user_limit: Option<u32>,
+    #[description = "Audio quality in kbps, capped by the server's boost level"]
+    #[min = 8]
+    #[max = 384]
+    bitrate: Option<u32>,
+    #[description = "Voice server region, leave out to let Discord pick"]
+    #[autocomplete = "autocomplete_region"]
+    region: Option<String>,
+    #[description = "Camera and screen share quality"] video_quality: Option<VideoQuality>,
+) -> Result<(), VoicersError> {
     info!("createvc command called");
+    // Looking up members and creating the channel can take longer than Discord's 3 seconds,
+    // so acknowledge now and send the real answer once we know how it went
+    ctx.defer_ephemeral().await?;
     // Clone vcname for the debug statement
     let vcname_for_debug = vcname.clone();
     debug!(
-        "received vctype: {} vcname: {} ping1: {} ping2: {} ping3: {} ping4: {} ping5: {}",
-        vctype,
+        "received vctype: {} preset: {} vcname: {} ping1: {} ping2: {} ping3: {} ping4: {} ping5: {}",
+        vctype.as_ref().unwrap_or(&"None".to_string()),
+        preset.as_ref().unwrap_or(&"None".to_string()),
         vcname_for_debug.as_ref().unwrap_or(&"None".to_string()),
         pingadd1.as_ref().unwrap_or(&"None".to_string()),
         pingadd2.as_ref().unwrap_or(&"None".to_string()),
@@ -104,17 +167,69 @@ pub async fn entrance(
         pingadd5.as_ref().unwrap_or(&"None".to_string())
     );
 
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
+    let guild_id = guild_only(ctx)?;
 
-    // verify that the VC name is not empty
-    // if it is then use the author's name, the type of VC, and the current time to create a unique name
-    let vcname = match vcname {
-        Some(name) => name,
-        None => format!("{}_{}_{}", ctx.author().name, vctype.to_lowercase(), now),
+    // Anything given explicitly wins over what the preset has saved
+    let preset = match preset {
+        Some(_) if !ctx.data().config().is_feature_enabled(Feature::VcPresets) => {
+            return Err(VoicersError::Permission(
+                "Presets are disabled on this server".to_string(),
+            ));
+        }
+        Some(name) => {
+            let name = presets::normalize_name(&name)?;
+            Some(
+                storage::preset(
+                    ctx.data().pool(),
+                    guild_id.get() as i64,
+                    ctx.author().id.get() as i64,
+                    &name,
+                )
+                .await?
+                .ok_or_else(|| {
+                    VoicersError::NotFound(format!("You don't have a preset called {}", name))
+                })?,
+            )
+        }
+        None => None,
     };
+    let vctype = vctype
+        .or_else(|| preset.as_ref().map(|preset| preset.vc_type.clone()))
+        .ok_or_else(|| {
+            VoicersError::Validation("Pick Private or Public, or use a preset".to_string())
+        })?;
+    // Before the limits, the name or the category, so a bad type never leaves anything behind
+    let vctype = VcType::parse(&vctype)?;
+    let feature = vctype.feature();
+    if !ctx.data().config().is_feature_enabled(feature) {
+        info!(
+            "{} is disabled, refusing to create a {} VC",
+            feature.name(),
+            vctype.name()
+        );
+        return Err(VoicersError::Permission(format!(
+            "{:?} voice channels are disabled on this server",
+            vctype
+        )));
+    }
+    let vcname = vcname.or_else(|| preset.as_ref().map(|preset| preset.vc_name.clone()));
+    if let Some(region) = &region {
+        if !RTC_REGIONS.contains(&region.as_str()) {
+            return Err(VoicersError::Validation(format!(
+                "{} is not a voice region, pick one from the list",
+                region
+            )));
+        }
+    }
+    // Arguments first, then the preset, then the server's defaults from the config
+    let settings = VoiceSettings {
+        user_limit,
+        bitrate: bitrate.map(discord_api::kbps_to_bitrate),
+        rtc_region: region,
+        video_quality: video_quality.map(Into::into),
+    }
+    .or(preset.as_ref().map(presets::settings).unwrap_or_default())
+    .or(VoiceSettings::from_config(&ctx.data().config().voice));
 
     let mut user_ids = Vec::new();
     let mut denied_users = Vec::new();
@@ -125,7 +240,7 @@ pub async fn entrance(
         &mut user_ids,
         &mut role_ids,
         &mut denied_users,
-        ctx.clone(),
+        ctx,
     )
     .await;
     process_mention(
@@ -133,7 +248,7 @@ pub async fn entrance(
         &mut user_ids,
         &mut role_ids,
         &mut denied_users,
-        ctx.clone(),
+        ctx,
     )
     .await;
     process_mention(
@@ -141,7 +256,7 @@ pub async fn entrance(
         &mut user_ids,
         &mut role_ids,
         &mut denied_users,
-        ctx.clone(),
+        ctx,
     )
     .await;
     process_mention(
@@ -149,7 +264,7 @@ pub async fn entrance(
         &mut user_ids,
         &mut role_ids,
         &mut denied_users,
-        ctx.clone(),
+        ctx,
     )
     .await;
     process_mention(
@@ -157,24 +272,59 @@ pub async fn entrance(
         &mut user_ids,
         &mut role_ids,
         &mut denied_users,
-        ctx.clone(),
+        ctx,
     )
     .await;
 
-    debug!("naming new VC as: {}", vcname);
+    // Preset invitees get the same role check as pinged ones, roles can change in between
+    if let Some(preset) = &preset {
+        for user_id in presets::invited_users(preset) {
+            process_user(user_id, &mut user_ids, &mut denied_users, ctx).await;
+        }
+        role_ids.extend(presets::invited_roles(preset));
+    }
 
-    create_voice_channel(ctx, &vcname, user_ids, role_ids, now, &vctype, denied_users).await?;
+    let owner_name = match ctx.author_member().await {
+        Some(member) => member.display_name().to_string(),
+        None => ctx.author().name.clone(),
+    };
+    let data = ctx.data().voicers();
+    let channel_id = lifecycle::create_vc(
+        data.api.as_ref(),
+        &data.pool,
+        data.config,
+        data.clock.as_ref(),
+        guild_id,
+        ctx.author().id,
+        CreateRequest {
+            vc_type: vctype,
+            name: vcname,
+            owner_name,
+            invited_users: user_ids,
+            invited_roles: role_ids,
+            settings,
+        },
+    )
+    .await?;
+
+    // Only now that the channel exists do we tell the user about it
+    let misc = &data.config.misc;
+    debug!("building denied users message");
+    let denied_users_message = build_denied_users_message(&denied_users);
+    ctx.send(poise::CreateReply::default()
+        .content(format!("{} \nCreated your new voice channel: {} \n The mods will have direct access to this channel \n Please be sure to follow all the rules and guidelines of the server {} \n{} \n{} \nCurrent Timeout: {} seconds",misc.vc_custom_prefix, channel_id.mention(),misc.vc_rules,misc.vc_custom_suffix,denied_users_message,data.config.voice.global_timeout))
+        .ephemeral(true)).await?;
 
     Ok(())
 }
 
 // Helper function to process a single mention
-async fn process_mention(
+async fn process_mention<D: VoicersData>(
     mention: Option<&String>,
     user_ids: &mut Vec<serenity::UserId>,
     role_ids: &mut Vec<serenity::RoleId>,
     denied_users: &mut Vec<String>,
-    ctx: discord::Context<'_>,
+    ctx: Context<'_, D>,
 ) {
     debug!("Processing mention: {:?}", mention);
     if let Some(mention) = mention {
@@ -196,58 +346,53 @@ async fn process_mention(
                 .trim_end_matches('>')
                 .parse::<u64>()
             {
-                let user_id = serenity::UserId::from(user_id);
-
-                // Retrieve the member and check their roles
-                if let Some(guild_id) = ctx.guild_id() {
-                    match guild_id.member(&ctx.discord().http, user_id).await {
-                        Ok(member) => {
-                            debug!("Found member in the guild.");
-
-                            let approved_role_ids = &config::get_config().misc.vc_mandatory_roles;
-
-                            // Convert the string role IDs to RoleId objects, ignoring invalid entries
-                            let approved_role_ids: Vec<serenity::RoleId> = approved_role_ids
-                                .iter()
-                                .filter_map(|id_str| {
-                                    if id_str.is_empty() {
-                                        None // Skip empty strings
-                                    } else {
-                                        // Parse the string as u64, and then convert to RoleId
-                                        id_str.parse::<u64>().ok().map(serenity::RoleId::from)
-                                    }
-                                })
-                                .collect();
-
-                            // Check if the member has any of the required roles
-                            let has_required_role = member
-                                .roles
-                                .iter()
-                                .any(|member_role_id| approved_role_ids.contains(member_role_id));
+                process_user(serenity::UserId::from(user_id), user_ids, denied_users, ctx).await;
+            }
+        }
+    }
+}
 
-                            if has_required_role {
-                                debug!("Member has a required role.");
-                                // Member has a required role, add to user_ids
-                                user_ids.push(serenity::UserId::from(user_id));
-                            } else {
-                                // Member doesn't have a required role, add to denied_users
-                                debug!("Member doesn't have a required role.");
-                                if let Some(nickname) = member.nick {
-                                    denied_users.push(nickname);
-                                } else {
-                                    denied_users.push(member.user.name);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            error!("Failed to find member in the guild: {:?}", e);
-                        }
-                    }
+// Invite a member if they have one of the mandatory roles, otherwise note them as denied
+async fn process_user<D: VoicersData>(
+    user_id: serenity::UserId,
+    user_ids: &mut Vec<serenity::UserId>,
+    denied_users: &mut Vec<String>,
+    ctx: Context<'_, D>,
+) {
+    // Retrieve the member and check their roles
+    if let Some(guild_id) = ctx.guild_id() {
+        match ctx
+            .data()
+            .voicers()
+            .api
+            .fetch_member(guild_id, user_id)
+            .await
+        {
+            Ok(member) => {
+                debug!("Found member in the guild.");
+
+                // Check if the member has any of the required roles
+                let has_required_role = lifecycle::has_mandatory_role(
+                    &member.roles,
+                    &ctx.data().config().misc.vc_mandatory_roles,
+                );
+
+                if has_required_role {
+                    debug!("Member has a required role.");
+                    // Member has a required role, add to user_ids
+                    user_ids.push(user_id);
                 } else {
-                    error!("Guild ID not found in the context.");
+                    // Member doesn't have a required role, add to denied_users
+                    debug!("Member doesn't have a required role.");
+                    denied_users.push(member.display_name().to_string());
                 }
             }
+            Err(e) => {
+                error!("Failed to find member in the guild: {:?}", e);
+            }
         }
+    } else {
+        error!("Guild ID not found in the context.");
     }
 }
 
@@ -260,165 +405,3 @@ fn build_denied_users_message(denied_users: &[String]) -> String {
     let user_list = denied_users.join(", ");
     format!("The following users were denied: {}", user_list)
 }
-
-async fn create_voice_channel(
-    ctx: discord::Context<'_>,
-    vcname: &str,
-    user_ids: Vec<serenity::UserId>, // Vector of up to 5 user IDs
-    role_ids: Vec<serenity::RoleId>, // Vector of role IDs
-    now: u64,
-    vctype: &str,
-    denied_users: Vec<String>,
-) -> Result<(), discord::Error> {
-    let vcmisc_config = config::get_vcmisc_config();
-    let vc_timeout = config::get_config().voice.global_timeout;
-
-    let vcrules = format!("{}", vcmisc_config.vc_rules);
-    let vccustomprefix = format!("{}", vcmisc_config.vc_custom_prefix);
-    let vccustomsuffix = format!("{}", vcmisc_config.vc_custom_suffix);
-    let vc_category = vcmisc_config.vc_category; // Retrieve the category ID from the config
-
-    debug!("building denied users message");
-    let denied_users_message = build_denied_users_message(&denied_users);
-
-    ctx.send(poise::CreateReply::default()
-        .content(format!("{} \nCreating a new voice channel named: {} \n The mods will have direct access to this channel \n Please be sure to follow all the rules and guidelines of the server {} \n{} \n{} \nCurrent Timeout: {} ms",vccustomprefix, vcname,vcrules,vccustomsuffix,denied_users_message,vc_timeout))
-        .ephemeral(true)).await?;
-
-    let mut permissions = Vec::new();
-
-    debug!("Adding permissions for users and roles");
-    // Permissions for specific users
-    for user_id in user_ids {
-        permissions.push(serenity::PermissionOverwrite {
-            allow: serenity::Permissions::VIEW_CHANNEL,
-            deny: serenity::Permissions::empty(),
-            kind: serenity::PermissionOverwriteType::Member(user_id),
-        });
-    }
-
-    // Permissions for specific roles
-    for role_id in role_ids {
-        permissions.push(serenity::PermissionOverwrite {
-            allow: serenity::Permissions::VIEW_CHANNEL,
-            deny: serenity::Permissions::empty(),
-            kind: serenity::PermissionOverwriteType::Role(role_id),
-        });
-    }
-
-    let moderator_role_ids = &config::get_config().moderation.moderator_roles;
-
-    // Permissions for moderator roles
-    for role_id_str in moderator_role_ids {
-        if let Ok(role_id) = role_id_str.parse::<u64>() {
-            let role_id = serenity::RoleId::from(role_id);
-            permissions.push(serenity::PermissionOverwrite {
-                allow: serenity::Permissions::VIEW_CHANNEL | serenity::Permissions::MANAGE_CHANNELS,
-                deny: serenity::Permissions::empty(),
-                kind: serenity::PermissionOverwriteType::Role(role_id),
-            });
-        }
-        // Optionally, handle the case where parsing fails
-    }
-
-    // Retrieve the guild ID
-    let guild_id = match ctx.guild_id() {
-        Some(id) => id,
-        None => {
-            // Handle the error appropriately, e.g., log an error and return
-            return Err(discord::Error::from("Command must be used in a guild."));
-        }
-    };
-
-    debug!("verifying VCTYPE");
-    //imagine forgetting to use to_lowercase() and having to debug for 2 hours
-    match vctype.to_lowercase().as_str() {
-        "private" => {
-            permissions.push(serenity::PermissionOverwrite {
-                allow: serenity::Permissions::empty(),
-                deny: serenity::Permissions::VIEW_CHANNEL,
-                kind: serenity::PermissionOverwriteType::Role(serenity::GuildId::everyone_role(
-                    &guild_id,
-                )),
-            });
-        }
-        "public" => {
-            permissions.push(serenity::PermissionOverwrite {
-                allow: serenity::Permissions::VIEW_CHANNEL,
-                deny: serenity::Permissions::empty(),
-                kind: serenity::PermissionOverwriteType::Role(serenity::GuildId::everyone_role(
-                    &guild_id,
-                )),
-            });
-        }
-        _ => {
-            return Err(discord::Error::from("Invalid type"));
-        }
-    }
-    // Permissions for the @everyone role
-    /*
-        permissions.push(serenity::PermissionOverwrite {
-            allow: serenity::Permissions::empty(),
-            deny: serenity::Permissions::VIEW_CHANNEL,
-            kind: serenity::PermissionOverwriteType::Role(serenity::GuildId::everyone_role(&guild_id)),
-        });
-    */
-
-    // Permission for the user who sent the request
-    permissions.push(serenity::PermissionOverwrite {
-        allow: serenity::Permissions::VIEW_CHANNEL,
-        deny: serenity::Permissions::empty(),
-        kind: serenity::PermissionOverwriteType::Member(ctx.author().id),
-    });
-
-    // Retrieve the bot's user ID
-    let bot_user_id = ctx.discord().cache.current_user().id;
-
-    // Permissions for the bot
-    permissions.push(serenity::PermissionOverwrite {
-        allow: serenity::Permissions::MANAGE_CHANNELS | serenity::Permissions::VIEW_CHANNEL,
-        deny: serenity::Permissions::empty(),
-        kind: serenity::PermissionOverwriteType::Member(bot_user_id),
-    });
-
-    debug!("Creating the channel builder");
-    // Creating the channel builder
-    let vc_builder = serenity::CreateChannel::new(vcname)
-        .kind(serenity::ChannelType::Voice) // Set the channel type to Voice
-        .category(serenity::ChannelId::from(vc_category)) // Set the category ID
-        .audit_log_reason("Bot created temporary channel") // Optional: Set the audit log reason
-        .permissions(permissions); // Optional: Set permissions
-
-    // Using the builder to create the channel
-    match guild_id.create_channel(&ctx, vc_builder).await {
-        Ok(channel) => {
-            // Assuming channel is of type GuildChannel
-            let channel_id_i64 = channel.id.get() as i64; // Convert ChannelId to i64
-
-            let pool = &ctx.data().pool;
-            let now_i64 = now as i64; // Convert `u64` to `i64`
-
-            debug!("Insert table query for channel ID: {}", channel_id_i64);
-            let insert_table_query = sqlx::query(
-                "INSERT INTO users (vc_id, guild_id, last_update, user_count) VALUES (?, ?, ?, ?)",
-            )
-            .bind(channel_id_i64)
-            .bind(i64::from(guild_id))
-            .bind(now_i64)
-            .bind(0);
-
-            // Execute the query
-            debug!("Executing insert table query");
-            match insert_table_query.execute(&**pool).await {
-                Ok(_) => debug!("Successfully inserted data into users table"),
-                Err(e) => error!("Failed to insert data into users table: {:?}", e),
-            }
-        }
-        Err(e) => {
-            error!("Failed to create voice channel: {:?}", e);
-            // Handle the error as needed
-        }
-    }
-
-    Ok(())
-}
@@ -1,6 +1,7 @@
+pub mod checks;
 pub mod contextmenu;
 pub mod createvc;
 pub mod help;
+pub mod nametemplate;
 pub mod ping;
-pub mod setmodrole;
-pub mod setusermod;
+pub mod vc;
@@ -0,0 +1,74 @@
+use crate::commands::checks::{guild_only, is_moderator};
+use crate::engine::{Context, VoicersData};
+use crate::error::VoicersError;
+use crate::naming::{self, NameParts};
+use crate::storage;
+use tracing::info;
+
+/// Set how new voice channels are named when no name is given
+#[poise::command(slash_command, guild_only, check = "is_moderator")]
+pub async fn nametemplate<D: VoicersData>(
+    ctx: Context<'_, D>,
+    #[description = "Placeholders: {owner} {type} {counter} {date} {activity}, leave empty to reset"]
+    template: Option<String>,
+) -> Result<(), VoicersError> {
+    let guild_id = guild_only(ctx)?;
+
+    // Whitespace only counts as a reset, same as leaving it out
+    let template = template
+        .map(|template| template.trim().to_string())
+        .filter(|template| !template.is_empty());
+    if let Some(template) = &template {
+        if template.chars().count() > naming::MAX_NAME_LENGTH {
+            return Err(VoicersError::Validation(format!(
+                "Templates can be at most {} characters long",
+                naming::MAX_NAME_LENGTH
+            )));
+        }
+    }
+
+    let data = ctx.data();
+    storage::set_name_template(
+        &data.voicers().pool,
+        guild_id.get() as i64,
+        template.as_deref(),
+    )
+    .await?;
+    info!(
+        guild_id = guild_id.get(),
+        template = template.as_deref().unwrap_or("(config default)"),
+        "Name template changed"
+    );
+
+    // Per server templates can't turn the presence intent on, so {activity} would stay empty
+    let voice = &data.config().voice;
+    let activity_warning = match &template {
+        Some(template) if template.contains("{activity}") && !naming::wants_presences(voice) => {
+            "\n{activity} will stay empty, the bot only sees activities when the name_template in its config uses it too"
+        }
+        _ => "",
+    };
+
+    // Show what a channel would end up being called, with made up values for the rest
+    let template = template.unwrap_or_else(|| voice.name_template.clone());
+    let example = naming::render(
+        &template,
+        &NameParts {
+            owner: &ctx.author().name,
+            vc_type: "private",
+            counter: 1,
+            now: data.voicers().clock.now(),
+            activity: Some("Minecraft"),
+        },
+    );
+    ctx.send(
+        poise::CreateReply::default()
+            .content(format!(
+                "New voice channels will be named like this: {}{}",
+                example, activity_warning
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
@@ -1,10 +1,12 @@
 use serenity::builder::CreateCommand;
 use serenity::model::application::ResolvedOption;
 
+#[allow(dead_code)]
 pub fn run(_options: &[ResolvedOption]) -> String {
     "Hey, I'm alive!".to_string()
 }
 
+#[allow(dead_code)]
 pub fn register() -> CreateCommand {
     CreateCommand::new("ping").description("A ping command")
 }
@@ -0,0 +1,314 @@
+use crate::commands::checks::{author_is_moderator, guild_only};
+use crate::config;
+use crate::discord_api::ChannelEdit;
+use crate::engine::{Context, VoicersData};
+use crate::error::VoicersError;
+use crate::knock::{Knock, KnockOutcome};
+use crate::{lifecycle, naming, presets, storage};
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use std::time::Duration;
+use tracing::info;
+
+/// Manage your temporary voice channel
+#[poise::command(slash_command, guild_only, subcommands("rename", "preset", "knock"))]
+pub async fn vc<D: VoicersData>(_ctx: Context<'_, D>) -> Result<(), VoicersError> {
+    // Discord only ever runs the subcommands
+    Ok(())
+}
+
+// The temp VC the author is sitting in, as long as it's theirs to change
+// Moderators can change anyone's, so the row is handed back for who really owns it
+async fn owned_channel<D: VoicersData>(
+    ctx: Context<'_, D>,
+) -> Result<(serenity::GuildId, serenity::ChannelId, storage::User), VoicersError> {
+    let guild_id = guild_only(ctx)?;
+    let data = ctx.data().voicers();
+
+    let not_in_vc =
+        || VoicersError::NotFound("Join your temporary voice channel first".to_string());
+    let channel_id = data
+        .api
+        .member_voice_channel(guild_id, ctx.author().id)
+        .ok_or_else(not_in_vc)?;
+    let row = storage::tracked_channel(&data.pool, channel_id.get() as i64)
+        .await?
+        .ok_or_else(not_in_vc)?;
+
+    let is_owner = row.owner_id == Some(ctx.author().id.get() as i64);
+    if !is_owner && !author_is_moderator(ctx).await {
+        return Err(VoicersError::Permission(
+            "Only the owner of this voice channel can do that".to_string(),
+        ));
+    }
+    Ok((guild_id, channel_id, row))
+}
+
+/// Rename the temporary voice channel you're in
+#[poise::command(slash_command, guild_only)]
+pub async fn rename<D: VoicersData>(
+    ctx: Context<'_, D>,
+    #[description = "New name of the voice channel"] name: String,
+) -> Result<(), VoicersError> {
+    // Discord rate limits renames hard, so this can sit waiting for a while
+    ctx.defer_ephemeral().await?;
+    let (guild_id, channel_id, _) = owned_channel(ctx).await?;
+    let name = naming::check_name(&name, &ctx.data().config().voice, guild_id, ctx.author().id)?;
+
+    ctx.data()
+        .voicers()
+        .api
+        .edit_channel(
+            channel_id,
+            ChannelEdit {
+                name: Some(name.clone()),
+                audit_reason: format!("Renamed by {}", ctx.author().name),
+                ..ChannelEdit::default()
+            },
+        )
+        .await?;
+    info!(
+        guild_id = guild_id.get(),
+        vc_id = channel_id.get(),
+        user_id = ctx.author().id.get(),
+        name = %name,
+        "Renamed voice channel"
+    );
+
+    ctx.send(
+        poise::CreateReply::default()
+            .content(format!("Renamed your voice channel to {}", name))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Save your voice channel setups to recreate them later
+#[poise::command(slash_command, guild_only, subcommands("preset_save", "preset_delete"))]
+pub async fn preset<D: VoicersData>(_ctx: Context<'_, D>) -> Result<(), VoicersError> {
+    Ok(())
+}
+
+/// Save the voice channel you're in as a preset for /createvc
+#[poise::command(slash_command, guild_only, rename = "save")]
+pub async fn preset_save<D: VoicersData>(
+    ctx: Context<'_, D>,
+    #[description = "Name to save it under, saving over an existing preset replaces it"]
+    name: String,
+) -> Result<(), VoicersError> {
+    let (guild_id, channel_id, row) = owned_channel(ctx).await?;
+    let name = presets::normalize_name(&name)?;
+
+    let data = ctx.data().voicers();
+    let guild = guild_id.get() as i64;
+    let user = ctx.author().id.get() as i64;
+    let existing = storage::preset_names(&data.pool, guild, user).await?;
+    if !existing.contains(&name) && existing.len() >= presets::MAX_PRESETS {
+        return Err(VoicersError::Validation(format!(
+            "You can have at most {} presets, delete one with /vc preset delete first",
+            presets::MAX_PRESETS
+        )));
+    }
+
+    let channel = data
+        .api
+        .guild_channels(guild_id)
+        .await?
+        .into_iter()
+        .find(|channel| channel.id == channel_id)
+        .ok_or_else(|| VoicersError::NotFound("That channel doesn't exist anymore".to_string()))?;
+    // A moderator saving someone else's VC shouldn't end up with its owner as an invitee
+    let owner_id = row
+        .owner_id
+        .map(|owner_id| serenity::UserId::new(owner_id as u64))
+        .unwrap_or(ctx.author().id);
+    let preset = presets::capture(&name, &channel, guild_id, owner_id);
+    storage::save_preset(&data.pool, guild, user, &preset).await?;
+    info!(
+        guild_id = guild_id.get(),
+        vc_id = channel_id.get(),
+        user_id = ctx.author().id.get(),
+        preset = %name,
+        "Saved voice channel preset"
+    );
+
+    ctx.send(
+        poise::CreateReply::default()
+            .content(format!(
+                "Saved as {}, use /createvc preset:{} to get it back",
+                name, name
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Delete one of your presets
+#[poise::command(slash_command, guild_only, rename = "delete")]
+pub async fn preset_delete<D: VoicersData>(
+    ctx: Context<'_, D>,
+    #[description = "Name of the preset"] name: String,
+) -> Result<(), VoicersError> {
+    let guild_id = guild_only(ctx)?;
+    let name = presets::normalize_name(&name)?;
+    let deleted = storage::delete_preset(
+        ctx.data().pool(),
+        guild_id.get() as i64,
+        ctx.author().id.get() as i64,
+        &name,
+    )
+    .await?;
+    if !deleted {
+        return Err(VoicersError::NotFound(format!(
+            "You don't have a preset called {}",
+            name
+        )));
+    }
+
+    ctx.send(
+        poise::CreateReply::default()
+            .content(format!("Deleted the {} preset", name))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Ask the owner of a private voice channel to let you in
+#[poise::command(slash_command, guild_only)]
+pub async fn knock<D: VoicersData>(
+    ctx: Context<'_, D>,
+    #[description = "The voice channel to knock on"]
+    #[channel_types("Voice")]
+    channel: Option<serenity::GuildChannel>,
+    #[description = "Or its owner, since private voice channels don't show up in the list"]
+    owner: Option<serenity::User>,
+) -> Result<(), VoicersError> {
+    // Looking up the channel and the knocker's roles can take longer than Discord gives a reply,
+    // and waiting on the owner certainly does
+    ctx.defer_ephemeral().await?;
+    let guild_id = guild_only(ctx)?;
+    let data = ctx.data().voicers();
+    let guild = guild_id.get() as i64;
+
+    let not_tracked = || VoicersError::NotFound("That isn't a temporary voice channel".to_string());
+    let row = match (channel, owner) {
+        (Some(channel), _) => storage::tracked_channel(&data.pool, channel.id.get() as i64)
+            .await?
+            .ok_or_else(not_tracked)?,
+        (None, Some(owner)) => {
+            let owned = storage::owned_channels(&data.pool, guild, owner.id.get() as i64).await?;
+            // The one they're sitting in if they have more than one
+            let current = data
+                .api
+                .member_voice_channel(guild_id, owner.id)
+                .map(|channel_id| channel_id.get() as i64);
+            let vc_id = owned
+                .iter()
+                .copied()
+                .find(|vc_id| Some(*vc_id) == current)
+                .or_else(|| owned.first().copied())
+                .ok_or_else(|| {
+                    VoicersError::NotFound(format!(
+                        "{} doesn't have a temporary voice channel",
+                        owner.name
+                    ))
+                })?;
+            storage::tracked_channel(&data.pool, vc_id)
+                .await?
+                .ok_or_else(not_tracked)?
+        }
+        (None, None) => {
+            return Err(VoicersError::Validation(
+                "Pick the voice channel or its owner to knock on".to_string(),
+            ))
+        }
+    };
+
+    let owner_id = row
+        .owner_id
+        .map(|owner_id| serenity::UserId::new(owner_id as u64))
+        .ok_or_else(|| {
+            VoicersError::NotFound("That voice channel doesn't have an owner to ask".to_string())
+        })?;
+    if owner_id == ctx.author().id {
+        return Err(VoicersError::Validation(
+            "That's your own voice channel".to_string(),
+        ));
+    }
+    let channel_id = serenity::ChannelId::new(row.vc_id as u64);
+    let channel = data
+        .api
+        .guild_channels(guild_id)
+        .await?
+        .into_iter()
+        .find(|channel| channel.id == channel_id)
+        .ok_or_else(not_tracked)?;
+    if !lifecycle::is_private(&channel, guild_id) {
+        return Err(VoicersError::Validation(format!(
+            "{} is public, you can just join it",
+            channel.name
+        )));
+    }
+
+    // No point bothering the owner about someone who couldn't get in anyway
+    let knock = Knock {
+        guild_id,
+        channel_id,
+        owner_id,
+        knocker_id: ctx.author().id,
+    };
+    // Released when the command returns, however it ends
+    let Some(_pending) = data.knocks.start(&knock) else {
+        return Err(VoicersError::Validation(format!(
+            "You're already knocking on {}, wait for the owner to answer",
+            channel.name
+        )));
+    };
+    let mandatory_roles = &ctx.data().config().misc.vc_mandatory_roles;
+    if !knock.allowed(data.api.as_ref(), mandatory_roles).await? {
+        return Err(VoicersError::Permission(
+            "You don't have a role that can join private voice channels".to_string(),
+        ));
+    }
+
+    let request = knock.send(data.api.as_ref()).await?;
+    ctx.send(
+        poise::CreateReply::default()
+            .content(format!(
+                "Knocked on {}, waiting for the owner to answer",
+                channel.name
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    let timeout = Duration::from_secs(
+        ctx.data()
+            .config()
+            .voice
+            .knock_timeout
+            .min(config::MAX_KNOCK_TIMEOUT),
+    );
+    let outcome = knock
+        .answer(data.api.as_ref(), &request, mandatory_roles, timeout)
+        .await?;
+    let reply = match outcome {
+        KnockOutcome::Approved => format!(
+            "You've been let into {}, join whenever",
+            channel_id.mention()
+        ),
+        KnockOutcome::Denied => format!("The owner of {} didn't let you in", channel.name),
+        KnockOutcome::Expired => format!(
+            "Nobody answered your knock on {}, try again later",
+            channel.name
+        ),
+        KnockOutcome::NotAllowed => {
+            "You don't have a role that can join private voice channels anymore".to_string()
+        }
+    };
+    ctx.send(poise::CreateReply::default().content(reply).ephemeral(true))
+        .await?;
+    Ok(())
+}
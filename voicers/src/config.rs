@@ -2,6 +2,7 @@ use colored::Colorize;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::{
+    fmt,
     str::FromStr,
     {fs, fs::OpenOptions},
     {io, io::Write},
@@ -10,14 +11,33 @@ use toml::Value;
 use tracing::{debug, level_filters::LevelFilter, warn};
 
 //expect root Table and configure subtables, osc
-#[derive(Serialize, Deserialize, Debug)]
+// Every section falls back to its Default impl so a missing table doesn't kill the whole file
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Config {
+    #[serde(default)]
     pub logging: Logging,
+    #[serde(default)]
     pub features: Features,
+    #[serde(default)]
     pub moderation: Moderation,
+    #[serde(default)]
     pub voice: Voice,
+    #[serde(default)]
     pub discord: Discord,
+    #[serde(default)]
     pub misc: Misc,
+    #[serde(default)]
+    pub http: Http,
+}
+
+impl Config {
+    pub fn is_feature_enabled(&self, feature: Feature) -> bool {
+        !self
+            .features
+            .disabled_features
+            .iter()
+            .any(|disabled| disabled == feature.name())
+    }
 }
 
 // This is a struct for the logging level
@@ -25,73 +45,556 @@ pub struct Config {
 pub struct Logging {
     #[serde(default = "default_logging_level")]
     pub level: String,
+    #[serde(default)]
+    pub format: LogFormat,
+    #[serde(default)]
+    pub filter: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_directory: Option<String>,
+    #[serde(default = "default_log_file_prefix")]
+    pub file_prefix: String,
+    #[serde(default)]
+    pub file_rotation: LogRotation,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Moderation {
-    #[serde(default = "default_moderator")]
+    #[serde(default)]
     pub moderator_roles: Vec<String>,
-    #[serde(default = "default_moderator")]
+    #[serde(default)]
     pub moderator_users: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Voice {
-    #[serde(default = "default_voice")]
+    #[serde(default = "default_voice_timeout")]
     pub global_timeout: u64,
+    #[serde(default)]
+    pub orphan_policy: OrphanPolicy,
+    #[serde(default = "default_name_template")]
+    pub name_template: String,
+    #[serde(default = "default_name_max_length")]
+    pub name_max_length: usize,
+    #[serde(default)]
+    pub name_blocklist: Vec<String>,
+    #[serde(default)]
+    pub name_patterns: Vec<String>,
+    #[serde(default)]
+    pub default_user_limit: Option<u32>,
+    #[serde(default)]
+    pub default_bitrate: Option<u32>,
+    #[serde(default)]
+    pub default_rtc_region: Option<String>,
+    #[serde(default)]
+    pub default_video_quality: Option<VideoQuality>,
+    #[serde(default)]
+    pub max_channels_per_user: u32,
+    #[serde(default)]
+    pub max_channels_per_guild: u32,
+    #[serde(default)]
+    pub create_cooldown: u64,
+    #[serde(default = "default_knock_timeout")]
+    pub knock_timeout: u64,
+}
+
+// Also offered as a choice on /createvc, so the names match the config's
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoQuality {
+    #[name = "auto"]
+    Auto,
+    #[name = "full"]
+    Full,
+}
+
+// What to do with temp VCs the bot created but no longer has in the database
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrphanPolicy {
+    #[default]
+    Adopt,
+    Delete,
+    Ignore,
 }
 
 // This is for disabled features
 // Wow I'm a real programmer now, I'm writing comments for my code
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Features {
-    #[serde(default = "default_features")]
+    #[serde(default)]
     pub disabled_features: Vec<String>,
 }
 
+// The optional HTTP server for metrics and health checks
 #[derive(Serialize, Deserialize, Debug)]
+pub struct Http {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_http_bind_address")]
+    pub bind_address: String,
+}
+
+// Every feature that can be switched off through disabled_features
+// The string names are what people write in the config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    CreateVcPrivate,
+    CreateVcPublic,
+    RulesMessage,
+    Reaper,
+    VcSync,
+    ModOverrides,
+    ContextMenu,
+    Help,
+    NameTemplate,
+    VcRename,
+    VcPresets,
+    VcKnock,
+}
+
+impl Feature {
+    pub const ALL: &'static [Feature] = &[
+        Feature::CreateVcPrivate,
+        Feature::CreateVcPublic,
+        Feature::RulesMessage,
+        Feature::Reaper,
+        Feature::VcSync,
+        Feature::ModOverrides,
+        Feature::ContextMenu,
+        Feature::Help,
+        Feature::NameTemplate,
+        Feature::VcRename,
+        Feature::VcPresets,
+        Feature::VcKnock,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::CreateVcPrivate => "createvc_private",
+            Feature::CreateVcPublic => "createvc_public",
+            Feature::RulesMessage => "rules_message",
+            Feature::Reaper => "reaper",
+            Feature::VcSync => "vc_sync",
+            Feature::ModOverrides => "mod_overrides",
+            Feature::ContextMenu => "context_menu",
+            Feature::Help => "help",
+            Feature::NameTemplate => "name_template",
+            Feature::VcRename => "vc_rename",
+            Feature::VcPresets => "vc_presets",
+            Feature::VcKnock => "vc_knock",
+        }
+    }
+}
+
+impl FromStr for Feature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Feature::ALL
+            .iter()
+            .copied()
+            .find(|feature| feature.name() == s)
+            .ok_or_else(|| format!("Unknown feature: {}", s))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Discord {
-    #[serde(default = "default_discord")]
-    pub bot_token: String,
+    #[serde(default)]
+    pub bot_token: Secret,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bot_token_file: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Discord {
+    // bot_token_file wins over bot_token so the token can live outside the config (docker secrets etc.)
+    pub fn token(&self) -> io::Result<Secret> {
+        match &self.bot_token_file {
+            Some(path) => Ok(Secret::new(fs::read_to_string(path)?.trim())),
+            None => Ok(self.bot_token.clone()),
+        }
+    }
+}
+
+// A string that never shows up in Debug or Display output
+// Serializing still writes the real value since that's how it gets back into config.toml
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Secret(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Misc {
-    #[serde(default = "default_discord")]
+    #[serde(default)]
     pub vc_rules: String,
-    #[serde(default = "default_discord")]
+    #[serde(default)]
     pub vc_custom_prefix: String,
-    #[serde(default = "default_discord")]
+    #[serde(default)]
     pub vc_custom_suffix: String,
-    #[serde(default = "default_features")]
+    #[serde(default)]
     pub vc_mandatory_roles: Vec<String>,
-    #[serde(default = "default_discord")]
+    #[serde(default)]
     pub vc_no_permission: String,
-    #[serde(default = "default_voice")]
+    #[serde(default)]
     pub vc_category: u64,
+    #[serde(default)]
+    pub vc_overflow_categories: Vec<u64>,
+    #[serde(default)]
+    pub vc_auto_category: bool,
 }
 
-// Default values for the config for the deserializer
-// These do not declare the default values in the file
-// just the values if the data isnt capable of being deseriazed properly
+impl Misc {
+    // vc_category first, then the overflow ones in order, leaving out the unset 0s
+    pub fn vc_categories(&self) -> Vec<u64> {
+        std::iter::once(self.vc_category)
+            .chain(self.vc_overflow_categories.iter().copied())
+            .filter(|id| *id != 0)
+            .collect()
+    }
+}
+
+// Default values for the config
+// These are used by the deserializer when a key is missing AND by the generated config file,
+// so there is exactly one place that decides what the defaults are
 fn default_logging_level() -> String {
-    "Debug".to_string()
+    "Info".to_string()
 }
 
-fn default_moderator() -> Vec<String> {
-    vec!["".to_string(), "".to_string()]
+fn default_log_file_prefix() -> String {
+    "voicers.log".to_string()
 }
 
-fn default_voice() -> u64 {
+fn default_voice_timeout() -> u64 {
     300
 }
 
-fn default_features() -> Vec<String> {
-    vec!["".to_string(), "".to_string()]
+fn default_name_template() -> String {
+    "{owner}'s {type} VC".to_string()
 }
 
-fn default_discord() -> String {
-    "".to_string()
+fn default_name_max_length() -> usize {
+    100
+}
+
+// Discord drops the interaction after 15 minutes, so a knock can't be answered any later than that
+pub const MAX_KNOCK_TIMEOUT: u64 = 900;
+
+fn default_knock_timeout() -> u64 {
+    120
+}
+
+fn default_http_bind_address() -> String {
+    "127.0.0.1:9090".to_string()
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging {
+            level: default_logging_level(),
+            format: LogFormat::default(),
+            filter: String::new(),
+            file_directory: None,
+            file_prefix: default_log_file_prefix(),
+            file_rotation: LogRotation::default(),
+        }
+    }
+}
+
+impl Default for Http {
+    fn default() -> Self {
+        Http {
+            enabled: false,
+            bind_address: default_http_bind_address(),
+        }
+    }
+}
+
+impl Default for Voice {
+    fn default() -> Self {
+        Voice {
+            global_timeout: default_voice_timeout(),
+            orphan_policy: OrphanPolicy::default(),
+            name_template: default_name_template(),
+            name_max_length: default_name_max_length(),
+            name_blocklist: Vec::new(),
+            name_patterns: Vec::new(),
+            default_user_limit: None,
+            default_bitrate: None,
+            default_rtc_region: None,
+            default_video_quality: None,
+            max_channels_per_user: 0,
+            max_channels_per_guild: 0,
+            create_cooldown: 0,
+            knock_timeout: default_knock_timeout(),
+        }
+    }
+}
+
+// Inline documentation for the generated config file
+// Each section lists its keys in the order they should be written, with the comment to put above them
+trait Documented {
+    const DOC: &'static str;
+    const FIELDS: &'static [(&'static str, &'static str)];
+}
+
+impl Documented for Logging {
+    const DOC: &'static str = "Logging settings for VoiceRS";
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        (
+            "level",
+            "This is the log level that VoiceRS will use.\noptions: Trace, Debug, Info, Warn, Error, Off",
+        ),
+        (
+            "format",
+            "How log lines are written\noptions: pretty (human readable), json (one object per line, for log aggregation)",
+        ),
+        (
+            "filter",
+            "Extra per-module filter directives in EnvFilter syntax, applied on top of level\nexample: \"voicers=debug,serenity=warn,sqlx=warn\"",
+        ),
+        (
+            "file_directory",
+            "Directory to also write log files into\nLeave unset to only log to stdout",
+        ),
+        ("file_prefix", "File name prefix for the log files"),
+        (
+            "file_rotation",
+            "How often a new log file is started\noptions: minutely, hourly, daily, never",
+        ),
+    ];
+}
+
+impl Documented for Features {
+    const DOC: &'static str = "Feature toggles";
+    const FIELDS: &'static [(&'static str, &'static str)] = &[(
+        "disabled_features",
+        "This defines features to be disabled\nNote that disabling features may have unpredictable behaviors\noptions:\n  createvc_private - /createvc with the Private type\n  createvc_public - /createvc with the Public type\n  rules_message - the rules message sent when someone joins a voice channel\n  reaper - deleting voice channels after they have been empty for global_timeout\n  vc_sync - periodically correcting the tracked user counts from Discord\n  mod_overrides - giving moderator_roles access to every temporary voice channel\n  context_menu - the User information context menu\n  help - the /help command\n  name_template - the /nametemplate command\n  vc_rename - /vc rename\n  vc_presets - /vc preset and the preset option of /createvc\n  vc_knock - /vc knock",
+    )];
+}
+
+impl Documented for Moderation {
+    const DOC: &'static str = "This is the moderator roles and users added by the server owner\nThis uses the snowflake ID of the role or user\nFor more info on snowflake IDs, see https://discord.com/developers/docs/reference#snowflakes";
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        (
+            "moderator_roles",
+            "Roles that can see and manage every temporary voice channel",
+        ),
+        ("moderator_users", "Users that are treated as moderators"),
+    ];
+}
+
+impl Documented for Voice {
+    const DOC: &'static str = "Voice channel lifecycle settings";
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        (
+            "global_timeout",
+            "This defines the Global Timeout period for voice channels\nThis is the time in seconds before an empty voice channel is automatically closed",
+        ),
+        (
+            "orphan_policy",
+            "What to do with voice channels in vc_category or vc_overflow_categories that the bot created but isn't tracking anymore\n(after a crash or a database reset)\noptions: adopt (track them again so they get cleaned up normally), delete, ignore",
+        ),
+        (
+            "name_template",
+            "The name for a new voice channel when the owner doesn't give one\nModerators can change it per server with /nametemplate\nplaceholders: {owner} {type} {counter} {date} {activity}\n{activity} needs the Presence Intent turned on for the bot in the Discord developer portal",
+        ),
+        (
+            "name_max_length",
+            "The longest name members can give a voice channel with /createvc or /vc rename\nDiscord's own limit is 100",
+        ),
+        (
+            "name_blocklist",
+            "Words that aren't allowed anywhere in a voice channel name, ignoring case",
+        ),
+        (
+            "name_patterns",
+            "Regular expressions for names that aren't allowed, ignoring case\nexample: [\"discord\\\\.gg/\", \"^admin\"]",
+        ),
+        (
+            "default_user_limit",
+            "How many members fit in a new voice channel unless the owner picks something else\n0 or unset means no limit, Discord allows at most 99",
+        ),
+        (
+            "default_bitrate",
+            "Bitrate in kbps for new voice channels unless the owner picks something else\nLowered automatically to what the server's boost level allows (96, 128, 256 or 384)",
+        ),
+        (
+            "default_rtc_region",
+            "Voice region for new voice channels, unset lets Discord pick the closest one\nexample: \"rotterdam\"",
+        ),
+        (
+            "default_video_quality",
+            "Video quality for new voice channels\noptions: auto, full (720p)",
+        ),
+        (
+            "max_channels_per_user",
+            "How many temporary voice channels one member can have open at once\n0 means no limit, which is the default. Set it to something like 2 to stop one member from filling the server",
+        ),
+        (
+            "max_channels_per_guild",
+            "How many temporary voice channels a server can have open at once\n0 means no limit",
+        ),
+        (
+            "create_cooldown",
+            "Seconds a member has to wait after creating a voice channel before creating another\n0 turns the cooldown off, which is the default. Set it to something like 30 to slow down spam",
+        ),
+        (
+            "knock_timeout",
+            "Seconds the owner of a private voice channel has to answer a /vc knock before it expires, at most 900",
+        ),
+    ];
+}
+
+impl Documented for Discord {
+    const DOC: &'static str = "Discord connection settings";
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        (
+            "bot_token",
+            "This defines the Discord token for the bot\nThis is required for the bot to function unless bot_token_file is set",
+        ),
+        (
+            "bot_token_file",
+            "Path to a file containing the Discord token\nWhen set this is used instead of bot_token",
+        ),
+    ];
+}
+
+impl Documented for Misc {
+    const DOC: &'static str = "Messages and channel placement for temporary voice channels";
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        (
+            "vc_rules",
+            "This defined the custom rules link for the bot\nThis is optional",
+        ),
+        (
+            "vc_custom_prefix",
+            "Text sent before the rules message",
+        ),
+        ("vc_custom_suffix", "Text sent after the rules message"),
+        (
+            "vc_mandatory_roles",
+            "Role IDs allowed to create voice channels and to be invited to private ones\nAdministrators are always allowed",
+        ),
+        (
+            "vc_no_permission",
+            "Message shown to members who are not allowed to create voice channels",
+        ),
+        (
+            "vc_category",
+            "The category ID new voice channels are created in",
+        ),
+        (
+            "vc_overflow_categories",
+            "More category IDs to use in order once vc_category is full\nDiscord allows 50 channels per category",
+        ),
+        (
+            "vc_auto_category",
+            "When a server has none of the categories above (unset, deleted, or the bot is in several servers),\ncreate a \"Temp Voice\" category there that moderator_roles can see, and keep using it\nWhen off, voice channels go to the top of the channel list instead",
+        ),
+    ];
+}
+
+impl Documented for Http {
+    const DOC: &'static str = "Optional HTTP server exposing Prometheus metrics on /metrics\nand health checks on /healthz (liveness) and /readyz (readiness)";
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        ("enabled", "Whether to start the HTTP server at all"),
+        (
+            "bind_address",
+            "Address and port the HTTP server listens on\nUse 0.0.0.0:9090 to make it reachable from other machines",
+        ),
+    ];
+}
+
+// Write one [section] of the generated config
+// The values come from serializing the section, so they can never drift from the Default impls
+fn write_section<T: Documented + Serialize>(out: &mut String, name: &str, section: &T) {
+    let table =
+        toml::Table::try_from(section).expect("Config sections always serialize to a table");
+
+    for line in T::DOC.lines() {
+        out.push_str(&format!("# {}\n", line));
+    }
+    out.push_str(&format!("[{}]\n", name));
+
+    for (key, doc) in T::FIELDS {
+        for line in doc.lines() {
+            out.push_str(&format!("# {}\n", line));
+        }
+        match table.get(*key) {
+            Some(value) => {
+                out.push_str(&format!("# default: {}\n", value));
+                out.push_str(&format!("{} = {}\n\n", key, value));
+            }
+            // Optional keys have no default and are left commented out
+            None => out.push_str(&format!("# {} =\n\n", key)),
+        }
+    }
+
+    // Anything that somebody forgot to document still has to end up in the file
+    for (key, value) in table.iter() {
+        if !T::FIELDS.iter().any(|(documented, _)| documented == key) {
+            out.push_str(&format!("{} = {}\n\n", key, value));
+        }
+    }
+}
+
+// Build the full default config file, comments and all
+pub fn default_config_toml() -> String {
+    let config = Config::default();
+    let mut out = String::new();
+
+    write_section(&mut out, "logging", &config.logging);
+    write_section(&mut out, "features", &config.features);
+    write_section(&mut out, "moderation", &config.moderation);
+    write_section(&mut out, "voice", &config.voice);
+    write_section(&mut out, "discord", &config.discord);
+    write_section(&mut out, "misc", &config.misc);
+    write_section(&mut out, "http", &config.http);
+
+    out
 }
 
 // Make CONFIG a public static so it's accessible from other modules
@@ -104,12 +607,7 @@ pub static CONFIG: Lazy<Config> = Lazy::new(|| {
                 config
             }
             Err(e) => {
-                println!(
-                    "{}{}{}",
-                    "Warn:".yellow().bold(),
-                    "Failed to parse config: ",
-                    e
-                );
+                println!("{}Failed to parse config: {}", "Warn:".yellow().bold(), e);
                 repair_config(config_str).expect("Failed to repair config");
                 let repaired_config_str =
                     fs::read_to_string(config_path).expect("Failed to read repaired config");
@@ -135,62 +633,21 @@ pub fn get_config() -> &'static Config {
 
 // Wow what a name, I wonder what this function is for
 fn create_config() -> io::Result<()> {
-    println!(
-        "{}{}",
-        "Info:".green().bold(),
-        "Creating a new config file..."
-    );
+    println!("{}Creating a new config file...", "Info:".green().bold());
+
+    write_default_config("config.toml", false)
+}
 
-    let mut config_file = fs::File::create("config.toml")?;
-
-    // This is the default config data that will be written to the file.
-    // My CoDE Is SelF DoCuMeNtInG
-    let config_data = r#"[logging]
-    # This is the log level that VoiceRS will use.
-    # Default: Info
-    level = "Info"
-
-    [features]
-    # This defines features to be disabled
-    # Note that disabling features may have unpredictable behaviors
-    # options: 
-    # default: ["",""]
-    disabled_features = ["",""]
-
-    [moderation]
-    # This is the moderator roles and users added by the server owner
-    # This uses the snowflake ID of the role or user
-    # For more info on snowflake IDs, see https://discord.com/developers/docs/reference#snowflakes
-    # default: ["",""]
-    moderator_roles = ["",""]
-    moderator_users = ["",""]
-
-    [voice]
-    # This defines the Global Timeout period for voice channels
-    # This is the time in seconds before a voice channel is automatically closed
-    # default: 300
-    global_timeout = 300
-
-    [discord]
-    # This defines the Discord token for the bot
-    # This is required for the bot to function
-    # default: ""
-    bot_token = ""
-
-    [misc]
-    # This defined the custom rules link for the bot
-    # This is optional
-    # default: ""
-    vc_rules = ""
-    vc_custom_prefix = ""
-    vc_custom_suffix = ""
-    vc_mandatory_roles = ["",""]
-    vc_no_permission = ""
-    vc_category = ""
-    "#;
-
-    let config_bytes = config_data.as_bytes();
-    config_file.write_all(config_bytes)?; //write default config
+// Write the generated default config to a path
+// Refuses to clobber an existing file unless told to
+pub fn write_default_config(path: &str, overwrite: bool) -> io::Result<()> {
+    let mut config_file = if overwrite {
+        fs::File::create(path)?
+    } else {
+        OpenOptions::new().write(true).create_new(true).open(path)?
+    };
+
+    config_file.write_all(default_config_toml().as_bytes())?; //write default config
     Ok(())
 }
 
@@ -200,20 +657,19 @@ pub fn get_logging_config() -> LevelFilter {
     // This might be unnecessary and could probably be directly called in the let level line
     let log_level_str = &CONFIG.logging.level;
 
-    // Parse the log level from string, defaulting to 'Debug' if there's an error
+    // Parse the log level from string, defaulting to 'Info' if there's an error
 
     let level_filter = LevelFilter::from_str(log_level_str).unwrap_or_else(|_| {
         eprintln!(
-            "Warn: Unable to parse log level from config: {}. Defaulting to 'Debug'",
+            "Warn: Unable to parse log level from config: {}. Defaulting to 'Info'",
             log_level_str
         );
-        LevelFilter::DEBUG
+        LevelFilter::INFO
     });
 
     println!(
-        "{}{}{:?}",
+        "{}Logging level: {:?}",
         "Info:".green().bold(),
-        "Logging level: ",
         level_filter
     );
 
@@ -223,7 +679,11 @@ pub fn get_logging_config() -> LevelFilter {
 // generate the features config for each feature implementation across the files
 pub fn get_features_config() -> &'static Features {
     &CONFIG.features
-    // eventually we might want to do some processing to verify the features are valid or not blank
+}
+
+// The one place everything asks before doing something that can be disabled
+pub fn is_feature_enabled(feature: Feature) -> bool {
+    CONFIG.is_feature_enabled(feature)
 }
 
 pub fn get_vcmisc_config() -> &'static Misc {
@@ -234,28 +694,54 @@ fn verify_config(config: &Config) {
     // Verify the logging level
     let log_level_str = &config.logging.level;
     if log_level_str.is_empty() {
-        println!("{}{}","Warn:".yellow().bold(), "Empty log level found in config\n This is not a valid log level and will be defaulted to 'Debug'");
+        println!("{}Empty log level found in config\n This is not a valid log level and will be defaulted to 'Info'", "Warn:".yellow().bold());
     }
 
     // Verify the features
     let features = &config.features.disabled_features;
     for feature in features {
         if feature.is_empty() {
-            println!("{}{}","Warn:".yellow().bold(), "Empty disabled feature found in config\n This is not a valid feature and will be ignored.");
+            println!("{}Empty disabled feature found in config\n This is not a valid feature and will be ignored.", "Warn:".yellow().bold());
+        } else if feature.parse::<Feature>().is_err() {
+            let known: Vec<&str> = Feature::ALL.iter().map(|feature| feature.name()).collect();
+            println!(
+                "{}Unknown disabled feature \"{}\" found in config\n This will be ignored. Valid features are: {}",
+                "Warn:".yellow().bold(),
+                feature,
+                known.join(", ")
+            );
         }
     }
 
     // Verify the voice timeout
     let voice_timeout = &config.voice.global_timeout;
     if *voice_timeout == 0 {
-        println!("{}{}","Warn:".yellow().bold(), "Invalid voice timeout found in config\n This is not a valid timeout and will be defaulted to 300 seconds.");
+        println!("{}Invalid voice timeout found in config\n This is not a valid timeout and will be defaulted to 300 seconds.", "Warn:".yellow().bold());
+    }
+
+    // Verify the name filter, a broken pattern would otherwise only show up when someone creates a VC
+    for pattern in &config.voice.name_patterns {
+        if let Err(e) = regex::Regex::new(pattern) {
+            println!(
+                "{}Invalid name pattern \"{}\" found in config\n This will be ignored: {}",
+                "Warn:".yellow().bold(),
+                pattern,
+                e
+            );
+        }
+    }
+    if config.voice.knock_timeout > MAX_KNOCK_TIMEOUT {
+        println!("{}knock_timeout above {} seconds found in config\n Knocks will expire after {} seconds, Discord doesn't allow answering them any later.", "Warn:".yellow().bold(), MAX_KNOCK_TIMEOUT, MAX_KNOCK_TIMEOUT);
+    }
+    if config.voice.name_max_length == 0 || config.voice.name_max_length > 100 {
+        println!("{}Invalid name_max_length found in config\n Names will be limited to Discord's 100 characters.", "Warn:".yellow().bold());
     }
 
     // Verify the moderator roles
     let moderator_roles = &config.moderation.moderator_roles;
     for role in moderator_roles {
         if role.is_empty() {
-            println!("{}{}","Warn:".yellow().bold(), "Empty moderator role found in config\n This is not a valid role and will be ignored.");
+            println!("{}Empty moderator role found in config\n This is not a valid role and will be ignored.", "Warn:".yellow().bold());
         }
     }
 
@@ -263,66 +749,41 @@ fn verify_config(config: &Config) {
     let moderator_users = &config.moderation.moderator_users;
     for user in moderator_users {
         if user.is_empty() {
-            println!("{}{}","Warn:".yellow().bold(), "Empty moderator user found in config\n This is not a valid user and will be ignored.");
+            println!("{}Empty moderator user found in config\n This is not a valid user and will be ignored.", "Warn:".yellow().bold());
         }
     }
 
     // Verify the discord token
     let discord_token = &config.discord.bot_token;
-    if discord_token.is_empty() {
-        println!("{}{}","ERROR:".red().bold(), "Empty discord token found in config\n This is not a valid token and will be ignored.\n This means the bot will not work.");
+    if let Some(token_file) = &config.discord.bot_token_file {
+        if let Err(e) = fs::metadata(token_file) {
+            println!(
+                "{}Unable to read bot_token_file {}: {}\n This means the bot will not work.",
+                "ERROR:".red().bold(),
+                token_file,
+                e
+            );
+        }
+    } else if discord_token.is_empty() {
+        println!("{}Empty discord token found in config\n This is not a valid token and will be ignored.\n This means the bot will not work.", "ERROR:".red().bold());
     }
 }
 
 // I hate this function
-// We abuse the errors to make it do what we want
+// Keep every section that still parses and reset the broken ones to their defaults
 fn repair_config(config_str: String) -> io::Result<()> {
-    println!(
-        "{}{}",
-        "Warn:".yellow().bold(),
-        "Repairing the Config file..."
-    );
-
-    let current_config_str = config_str;
-
-    let logging: Logging = toml::from_str(&current_config_str).unwrap_or_else(|_| Logging {
-        level: default_logging_level(),
-    });
-
-    let features: Features = toml::from_str(&current_config_str).unwrap_or_else(|_| Features {
-        disabled_features: default_features(),
-    });
-
-    let moderation: Moderation =
-        toml::from_str(&current_config_str).unwrap_or_else(|_| Moderation {
-            moderator_roles: default_moderator(),
-            moderator_users: default_moderator(),
-        });
+    println!("{}Repairing the Config file...", "Warn:".yellow().bold());
 
-    let voice: Voice = toml::from_str(&current_config_str).unwrap_or_else(|_| Voice {
-        global_timeout: default_voice(),
-    });
-
-    let discord: Discord = toml::from_str(&current_config_str).unwrap_or_else(|_| Discord {
-        bot_token: default_discord(),
-    });
-
-    let misc: Misc = toml::from_str(&current_config_str).unwrap_or_else(|_| Misc {
-        vc_rules: default_discord(),
-        vc_custom_prefix: default_discord(),
-        vc_custom_suffix: default_discord(),
-        vc_mandatory_roles: default_features(),
-        vc_no_permission: default_discord(),
-        vc_category: default_voice(),
-    });
+    let current_config = toml::from_str::<toml::Table>(&config_str).unwrap_or_default();
 
     let rebuilt_config = Config {
-        logging: logging,
-        features: features,
-        moderation: moderation,
-        voice: voice,
-        discord: discord,
-        misc: misc,
+        logging: repair_section(&current_config, "logging"),
+        features: repair_section(&current_config, "features"),
+        moderation: repair_section(&current_config, "moderation"),
+        voice: repair_section(&current_config, "voice"),
+        discord: repair_section(&current_config, "discord"),
+        misc: repair_section(&current_config, "misc"),
+        http: repair_section(&current_config, "http"),
     };
 
     let mut file = OpenOptions::new()
@@ -334,6 +795,20 @@ fn repair_config(config_str: String) -> io::Result<()> {
     Ok(())
 }
 
+fn repair_section<T: serde::de::DeserializeOwned + Default>(config: &toml::Table, name: &str) -> T {
+    config
+        .get(name)
+        .and_then(|section| section.clone().try_into().ok())
+        .unwrap_or_else(|| {
+            println!(
+                "{}Resetting [{}] to its defaults",
+                "Warn:".yellow().bold(),
+                name
+            );
+            T::default()
+        })
+}
+
 // Internal function to update the config after a scan
 // Might be useful for a future feature to update the config without restarting the bot
 // which matters since this is a Discord bot
@@ -395,3 +870,95 @@ pub fn _update_config(key: &str, value: &str, operation: &str) -> io::Result<()>
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_never_print_their_value() {
+        let secret = Secret::new("super secret token");
+        assert!(!format!("{:?}", secret).contains("super secret"));
+        assert!(!format!("{}", secret).contains("super secret"));
+
+        let discord = Discord {
+            bot_token: secret.clone(),
+            bot_token_file: None,
+        };
+        assert!(!format!("{:?}", discord).contains("super secret"));
+        assert_eq!(discord.token().unwrap().expose(), "super secret token");
+    }
+
+    #[test]
+    fn token_file_is_read_and_trimmed() {
+        let path = std::env::temp_dir().join(format!("voicers_token_{}", std::process::id()));
+        fs::write(&path, "  file token\n").unwrap();
+        let discord = Discord {
+            bot_token: Secret::new("config token"),
+            bot_token_file: Some(path.to_string_lossy().into_owned()),
+        };
+
+        let token = discord.token();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(token.unwrap().expose(), "file token");
+    }
+
+    #[test]
+    fn missing_token_file_is_an_error() {
+        let discord = Discord {
+            bot_token: Secret::default(),
+            bot_token_file: Some("/nonexistent/voicers/token".to_string()),
+        };
+        assert_eq!(discord.token().unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn feature_names_parse_back_and_unknown_ones_are_rejected() {
+        for feature in Feature::ALL {
+            assert_eq!(feature.name().parse::<Feature>(), Ok(*feature));
+        }
+        assert!("createvc".parse::<Feature>().is_err());
+        assert!("Reaper".parse::<Feature>().is_err());
+        assert!("".parse::<Feature>().is_err());
+    }
+
+    #[test]
+    fn disabled_features_are_matched_by_name() {
+        let mut config = Config::default();
+        assert!(config.is_feature_enabled(Feature::Reaper));
+
+        config.features.disabled_features = vec!["reaper".to_string(), "nonsense".to_string()];
+        assert!(!config.is_feature_enabled(Feature::Reaper));
+        assert!(config.is_feature_enabled(Feature::VcSync));
+    }
+
+    #[test]
+    fn generated_config_parses_back_into_the_defaults() {
+        let generated = default_config_toml();
+        let parsed: Config = toml::from_str(&generated).expect("generated config should parse");
+
+        // Comparing the serialized forms covers every section without needing PartialEq everywhere
+        assert_eq!(
+            toml::to_string(&parsed).unwrap(),
+            toml::to_string(&Config::default()).unwrap()
+        );
+        // Every documented key made it into the file, commented out or not
+        for (key, _) in Voice::FIELDS {
+            assert!(
+                generated.contains(&format!("{} =", key)),
+                "{} is missing",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn create_limits_are_off_unless_configured() {
+        let voice: Voice = toml::from_str("").unwrap();
+        assert_eq!(voice.max_channels_per_user, 0);
+        assert_eq!(voice.max_channels_per_guild, 0);
+        assert_eq!(voice.create_cooldown, 0);
+        assert_eq!(Voice::default().max_channels_per_user, 0);
+        assert_eq!(Voice::default().create_cooldown, 0);
+    }
+}
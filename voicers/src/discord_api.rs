@@ -0,0 +1,593 @@
+use crate::config::{self, VideoQuality};
+use async_trait::async_trait;
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+// The Discord operations the bot needs
+// Going through this instead of serenity directly is what lets the core logic run against a fake guild
+#[async_trait]
+pub trait DiscordApi: Send + Sync {
+    async fn create_voice_channel(
+        &self,
+        guild_id: serenity::GuildId,
+        channel: NewVoiceChannel,
+    ) -> Result<serenity::ChannelId, serenity::Error>;
+
+    async fn create_category(
+        &self,
+        guild_id: serenity::GuildId,
+        name: &str,
+        permissions: Vec<serenity::PermissionOverwrite>,
+        audit_reason: &str,
+    ) -> Result<serenity::ChannelId, serenity::Error>;
+
+    async fn delete_channel(
+        &self,
+        channel_id: serenity::ChannelId,
+        reason: &str,
+    ) -> Result<(), serenity::Error>;
+
+    async fn edit_channel(
+        &self,
+        channel_id: serenity::ChannelId,
+        edit: ChannelEdit,
+    ) -> Result<(), serenity::Error>;
+
+    async fn guild_channels(
+        &self,
+        guild_id: serenity::GuildId,
+    ) -> Result<Vec<ChannelInfo>, serenity::Error>;
+
+    async fn fetch_member(
+        &self,
+        guild_id: serenity::GuildId,
+        user_id: serenity::UserId,
+    ) -> Result<MemberInfo, serenity::Error>;
+
+    // Only works for members already connected to voice, like Discord itself
+    async fn move_member(
+        &self,
+        guild_id: serenity::GuildId,
+        user_id: serenity::UserId,
+        channel_id: serenity::ChannelId,
+    ) -> Result<(), serenity::Error>;
+
+    async fn send_message(
+        &self,
+        channel_id: serenity::ChannelId,
+        content: &str,
+    ) -> Result<(), serenity::Error>;
+
+    // Ask a VC's owner to let someone in, with buttons to answer
+    // By DM, or in the VC's own chat when the owner has DMs closed
+    async fn send_knock(
+        &self,
+        owner_id: serenity::UserId,
+        channel_id: serenity::ChannelId,
+        content: &str,
+    ) -> Result<KnockRequest, serenity::Error>;
+
+    // The next button press on a knock request, from whoever pressed it
+    // Presses are acknowledged right away, the answer shows up once close_knock edits the message
+    // None once the timeout runs out
+    async fn next_knock_press(
+        &self,
+        request: &KnockRequest,
+        timeout: Duration,
+    ) -> Option<KnockPress>;
+
+    // Replace the request's text and take the buttons away
+    async fn close_knock(
+        &self,
+        request: &KnockRequest,
+        content: &str,
+    ) -> Result<(), serenity::Error>;
+
+    fn current_user_id(&self) -> serenity::UserId;
+
+    // The guilds the bot is in
+    fn guild_ids(&self) -> Vec<serenity::GuildId>;
+
+    // What the member is playing or streaming, if Discord told us
+    fn member_activity(
+        &self,
+        guild_id: serenity::GuildId,
+        user_id: serenity::UserId,
+    ) -> Option<String>;
+
+    // The guild's boost level, which caps the bitrate of its voice channels
+    fn premium_tier(&self, guild_id: serenity::GuildId) -> serenity::PremiumTier;
+
+    // The voice channel a member is connected to right now, from the voice states we've seen
+    fn member_voice_channel(
+        &self,
+        guild_id: serenity::GuildId,
+        user_id: serenity::UserId,
+    ) -> Option<serenity::ChannelId>;
+
+    // How many people are in a voice channel right now, from the voice states we've seen
+    async fn voice_channel_user_count(
+        &self,
+        guild_id: serenity::GuildId,
+        channel_id: serenity::ChannelId,
+    ) -> Result<usize, serenity::Error>;
+}
+
+// Where a knock request ended up, a DM or the VC's chat
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KnockRequest {
+    pub channel_id: serenity::ChannelId,
+    pub message_id: serenity::MessageId,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KnockPress {
+    pub user_id: serenity::UserId,
+    pub approved: bool,
+}
+
+// The knock buttons' custom ids, the collector tells requests apart by message
+const KNOCK_APPROVE: &str = "knock_approve";
+const KNOCK_DENY: &str = "knock_deny";
+
+// Everything needed to create a temporary voice channel
+#[derive(Clone, Debug)]
+pub struct NewVoiceChannel {
+    pub name: String,
+    pub category: Option<serenity::ChannelId>,
+    pub permissions: Vec<serenity::PermissionOverwrite>,
+    pub settings: VoiceSettings,
+    pub audit_reason: String,
+}
+
+// The voice specific knobs of a channel, None leaves it to Discord's default
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VoiceSettings {
+    pub user_limit: Option<u32>,
+    // In bits per second like Discord has it, the config and commands use kbps
+    pub bitrate: Option<u32>,
+    pub rtc_region: Option<String>,
+    pub video_quality: Option<serenity::VideoQualityMode>,
+}
+
+impl VoiceSettings {
+    pub fn from_config(voice: &config::Voice) -> Self {
+        VoiceSettings {
+            user_limit: voice.default_user_limit,
+            bitrate: voice.default_bitrate.map(kbps_to_bitrate),
+            rtc_region: voice.default_rtc_region.clone(),
+            video_quality: voice.default_video_quality.map(Into::into),
+        }
+    }
+
+    // Fill in whatever this leaves open from another set, field by field
+    pub fn or(self, fallback: VoiceSettings) -> Self {
+        VoiceSettings {
+            user_limit: self.user_limit.or(fallback.user_limit),
+            bitrate: self.bitrate.or(fallback.bitrate),
+            rtc_region: self.rtc_region.or(fallback.rtc_region),
+            video_quality: self.video_quality.or(fallback.video_quality),
+        }
+    }
+}
+
+impl From<VideoQuality> for serenity::VideoQualityMode {
+    fn from(quality: VideoQuality) -> Self {
+        match quality {
+            VideoQuality::Auto => serenity::VideoQualityMode::Auto,
+            VideoQuality::Full => serenity::VideoQualityMode::Full,
+        }
+    }
+}
+
+// The most a voice channel can have at a boost level, in bits per second
+// https://support.discord.com/hc/en-us/articles/360028038352
+pub fn max_bitrate(tier: serenity::PremiumTier) -> u32 {
+    match tier {
+        serenity::PremiumTier::Tier1 => 128_000,
+        serenity::PremiumTier::Tier2 => 256_000,
+        serenity::PremiumTier::Tier3 => 384_000,
+        _ => 96_000,
+    }
+}
+
+// Saturates instead of wrapping, whatever comes out gets clamped to max_bitrate before use anyway
+pub fn kbps_to_bitrate(kbps: u32) -> u32 {
+    kbps.saturating_mul(1000)
+}
+
+// Discord's floor is 8kbps
+pub const MIN_BITRATE: u32 = 8_000;
+
+// Discord refuses anything outside what the boost level allows
+pub fn clamp_bitrate(bitrate: u32, tier: serenity::PremiumTier) -> u32 {
+    bitrate.clamp(MIN_BITRATE, max_bitrate(tier))
+}
+
+// Changes to an existing channel, anything left as None stays the way it is
+#[derive(Clone, Debug, Default)]
+pub struct ChannelEdit {
+    pub name: Option<String>,
+    pub permissions: Option<Vec<serenity::PermissionOverwrite>>,
+    pub audit_reason: String,
+}
+
+// The parts of a guild channel the bot cares about
+// Our own type since serenity's GuildChannel can't be built outside serenity
+#[derive(Clone, Debug)]
+pub struct ChannelInfo {
+    pub id: serenity::ChannelId,
+    pub name: String,
+    pub kind: serenity::ChannelType,
+    pub parent_id: Option<serenity::ChannelId>,
+    pub permission_overwrites: Vec<serenity::PermissionOverwrite>,
+    pub settings: VoiceSettings,
+}
+
+impl From<serenity::GuildChannel> for ChannelInfo {
+    fn from(channel: serenity::GuildChannel) -> Self {
+        ChannelInfo {
+            id: channel.id,
+            name: channel.name,
+            kind: channel.kind,
+            parent_id: channel.parent_id,
+            permission_overwrites: channel.permission_overwrites,
+            settings: VoiceSettings {
+                // Discord sends 0 for no limit
+                user_limit: channel.user_limit.filter(|limit| *limit > 0),
+                bitrate: channel.bitrate,
+                rtc_region: channel.rtc_region,
+                video_quality: channel.video_quality_mode,
+            },
+        }
+    }
+}
+
+// The parts of a guild member the bot cares about
+#[derive(Clone, Debug)]
+pub struct MemberInfo {
+    pub name: String,
+    pub nick: Option<String>,
+    pub roles: Vec<serenity::RoleId>,
+}
+
+impl MemberInfo {
+    pub fn display_name(&self) -> &str {
+        self.nick.as_deref().unwrap_or(&self.name)
+    }
+}
+
+impl From<serenity::Member> for MemberInfo {
+    fn from(member: serenity::Member) -> Self {
+        MemberInfo {
+            name: member.user.name,
+            nick: member.nick,
+            roles: member.roles,
+        }
+    }
+}
+
+// The real thing, backed by serenity's HTTP client and cache
+// The shard is what button presses come in on, the bot only ever runs the one
+pub struct SerenityApi {
+    http: Arc<serenity::Http>,
+    cache: Arc<serenity::Cache>,
+    shard: serenity::ShardMessenger,
+}
+
+impl SerenityApi {
+    pub fn new(
+        http: Arc<serenity::Http>,
+        cache: Arc<serenity::Cache>,
+        shard: serenity::ShardMessenger,
+    ) -> Self {
+        SerenityApi { http, cache, shard }
+    }
+}
+
+#[async_trait]
+impl DiscordApi for SerenityApi {
+    async fn create_voice_channel(
+        &self,
+        guild_id: serenity::GuildId,
+        channel: NewVoiceChannel,
+    ) -> Result<serenity::ChannelId, serenity::Error> {
+        let mut builder = serenity::CreateChannel::new(channel.name)
+            .kind(serenity::ChannelType::Voice)
+            .audit_log_reason(&channel.audit_reason)
+            .permissions(channel.permissions);
+        if let Some(category) = channel.category {
+            builder = builder.category(category);
+        }
+        if let Some(user_limit) = channel.settings.user_limit {
+            builder = builder.user_limit(user_limit);
+        }
+        if let Some(bitrate) = channel.settings.bitrate {
+            builder = builder.bitrate(bitrate);
+        }
+        if let Some(rtc_region) = channel.settings.rtc_region {
+            builder = builder.rtc_region(rtc_region);
+        }
+        if let Some(video_quality) = channel.settings.video_quality {
+            builder = builder.video_quality_mode(video_quality);
+        }
+
+        let created = guild_id.create_channel(&self.http, builder).await?;
+        Ok(created.id)
+    }
+
+    async fn create_category(
+        &self,
+        guild_id: serenity::GuildId,
+        name: &str,
+        permissions: Vec<serenity::PermissionOverwrite>,
+        audit_reason: &str,
+    ) -> Result<serenity::ChannelId, serenity::Error> {
+        let builder = serenity::CreateChannel::new(name)
+            .kind(serenity::ChannelType::Category)
+            .audit_log_reason(audit_reason)
+            .permissions(permissions);
+        let created = guild_id.create_channel(&self.http, builder).await?;
+        Ok(created.id)
+    }
+
+    async fn delete_channel(
+        &self,
+        channel_id: serenity::ChannelId,
+        reason: &str,
+    ) -> Result<(), serenity::Error> {
+        self.http.delete_channel(channel_id, Some(reason)).await?;
+        Ok(())
+    }
+
+    async fn edit_channel(
+        &self,
+        channel_id: serenity::ChannelId,
+        edit: ChannelEdit,
+    ) -> Result<(), serenity::Error> {
+        let mut builder = serenity::EditChannel::new().audit_log_reason(&edit.audit_reason);
+        if let Some(name) = edit.name {
+            builder = builder.name(name);
+        }
+        if let Some(permissions) = edit.permissions {
+            builder = builder.permissions(permissions);
+        }
+
+        channel_id.edit(&self.http, builder).await?;
+        Ok(())
+    }
+
+    async fn guild_channels(
+        &self,
+        guild_id: serenity::GuildId,
+    ) -> Result<Vec<ChannelInfo>, serenity::Error> {
+        let channels = guild_id.channels(&self.http).await?;
+        Ok(channels.into_values().map(ChannelInfo::from).collect())
+    }
+
+    async fn fetch_member(
+        &self,
+        guild_id: serenity::GuildId,
+        user_id: serenity::UserId,
+    ) -> Result<MemberInfo, serenity::Error> {
+        let member = guild_id.member(&self.http, user_id).await?;
+        Ok(MemberInfo::from(member))
+    }
+
+    async fn move_member(
+        &self,
+        guild_id: serenity::GuildId,
+        user_id: serenity::UserId,
+        channel_id: serenity::ChannelId,
+    ) -> Result<(), serenity::Error> {
+        guild_id
+            .move_member(&self.http, user_id, channel_id)
+            .await?;
+        Ok(())
+    }
+
+    async fn send_message(
+        &self,
+        channel_id: serenity::ChannelId,
+        content: &str,
+    ) -> Result<(), serenity::Error> {
+        channel_id.say(&self.http, content).await?;
+        Ok(())
+    }
+
+    async fn send_knock(
+        &self,
+        owner_id: serenity::UserId,
+        channel_id: serenity::ChannelId,
+        content: &str,
+    ) -> Result<KnockRequest, serenity::Error> {
+        let buttons = vec![serenity::CreateActionRow::Buttons(vec![
+            serenity::CreateButton::new(KNOCK_APPROVE)
+                .label("Let them in")
+                .style(serenity::ButtonStyle::Success),
+            serenity::CreateButton::new(KNOCK_DENY)
+                .label("Deny")
+                .style(serenity::ButtonStyle::Danger),
+        ])];
+
+        let dm = owner_id
+            .direct_message(
+                &self.http,
+                serenity::CreateMessage::new()
+                    .content(content)
+                    .components(buttons.clone()),
+            )
+            .await;
+        let message = match dm {
+            Ok(message) => message,
+            Err(_) => {
+                channel_id
+                    .send_message(
+                        &self.http,
+                        serenity::CreateMessage::new()
+                            .content(format!("{}, {}", owner_id.mention(), content))
+                            .components(buttons)
+                            .allowed_mentions(
+                                serenity::CreateAllowedMentions::new().users([owner_id]),
+                            ),
+                    )
+                    .await?
+            }
+        };
+        Ok(KnockRequest {
+            channel_id: message.channel_id,
+            message_id: message.id,
+        })
+    }
+
+    async fn next_knock_press(
+        &self,
+        request: &KnockRequest,
+        timeout: Duration,
+    ) -> Option<KnockPress> {
+        let press = serenity::ComponentInteractionCollector::new(&self.shard)
+            .message_id(request.message_id)
+            .filter(|press| {
+                press.data.custom_id == KNOCK_APPROVE || press.data.custom_id == KNOCK_DENY
+            })
+            .timeout(timeout)
+            .await?;
+
+        // Discord shows the press as failed unless it hears back within 3 seconds
+        if let Err(why) = press
+            .create_response(&self.http, serenity::CreateInteractionResponse::Acknowledge)
+            .await
+        {
+            warn!(error = ?why, "Failed to acknowledge a knock button press");
+        }
+        Some(KnockPress {
+            user_id: press.user.id,
+            approved: press.data.custom_id == KNOCK_APPROVE,
+        })
+    }
+
+    async fn close_knock(
+        &self,
+        request: &KnockRequest,
+        content: &str,
+    ) -> Result<(), serenity::Error> {
+        request
+            .channel_id
+            .edit_message(
+                &self.http,
+                request.message_id,
+                serenity::EditMessage::new()
+                    .content(content)
+                    .components(Vec::new()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    fn current_user_id(&self) -> serenity::UserId {
+        self.cache.current_user().id
+    }
+
+    fn guild_ids(&self) -> Vec<serenity::GuildId> {
+        self.cache.guilds()
+    }
+
+    // Presences are only cached with the presence intent, otherwise this is always None
+    fn member_activity(
+        &self,
+        guild_id: serenity::GuildId,
+        user_id: serenity::UserId,
+    ) -> Option<String> {
+        let guild = self.cache.guild(guild_id)?;
+        let presence = guild.presences.get(&user_id)?;
+        // A custom status is just text, the game or stream is what we want
+        presence
+            .activities
+            .iter()
+            .find(|activity| activity.kind != serenity::ActivityType::Custom)
+            .map(|activity| activity.name.clone())
+    }
+
+    // Guilds missing from the cache get the unboosted limits, which is always safe
+    fn premium_tier(&self, guild_id: serenity::GuildId) -> serenity::PremiumTier {
+        self.cache
+            .guild(guild_id)
+            .map(|guild| guild.premium_tier)
+            .unwrap_or_default()
+    }
+
+    fn member_voice_channel(
+        &self,
+        guild_id: serenity::GuildId,
+        user_id: serenity::UserId,
+    ) -> Option<serenity::ChannelId> {
+        self.cache
+            .guild(guild_id)?
+            .voice_states
+            .get(&user_id)
+            .and_then(|voice_state| voice_state.channel_id)
+    }
+
+    async fn voice_channel_user_count(
+        &self,
+        guild_id: serenity::GuildId,
+        channel_id: serenity::ChannelId,
+    ) -> Result<usize, serenity::Error> {
+        // A guild missing from the cache means we can't know, which is not the same as empty
+        let guild = self
+            .cache
+            .guild(guild_id)
+            .ok_or(serenity::Error::Other("Guild not found in cache"))?;
+        Ok(guild
+            .voice_states
+            .values()
+            .filter(|voice_state| voice_state.channel_id == Some(channel_id))
+            .count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explicit_settings_win_over_the_config_field_by_field() {
+        let voice = config::Voice {
+            default_user_limit: Some(5),
+            default_bitrate: Some(64),
+            default_video_quality: Some(VideoQuality::Full),
+            ..config::Voice::default()
+        };
+        let explicit = VoiceSettings {
+            user_limit: Some(0),
+            rtc_region: Some("rotterdam".to_string()),
+            ..VoiceSettings::default()
+        };
+
+        assert_eq!(
+            explicit.or(VoiceSettings::from_config(&voice)),
+            VoiceSettings {
+                user_limit: Some(0),
+                bitrate: Some(64_000),
+                rtc_region: Some("rotterdam".to_string()),
+                video_quality: Some(serenity::VideoQualityMode::Full),
+            }
+        );
+    }
+
+    #[test]
+    fn huge_bitrates_clamp_instead_of_wrapping() {
+        let voice = config::Voice {
+            default_bitrate: Some(5_000_000),
+            ..config::Voice::default()
+        };
+        let bitrate = VoiceSettings::from_config(&voice).bitrate.unwrap();
+        assert_eq!(bitrate, u32::MAX);
+        assert_eq!(
+            clamp_bitrate(bitrate, serenity::PremiumTier::Tier2),
+            256_000
+        );
+    }
+}
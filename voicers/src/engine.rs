@@ -0,0 +1,264 @@
+use crate::clock::{Clock, SystemClock};
+use crate::commands;
+use crate::config::{Config, Feature};
+use crate::discord_api::DiscordApi;
+use crate::error::VoicersError;
+use crate::health::Health;
+use crate::knock::PendingKnocks;
+use crate::reaper::{self, ReaperSettings};
+use crate::supervisor::Supervisor;
+use crate::{lifecycle, metrics};
+use poise::serenity_prelude as serenity;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info};
+
+// The pieces for mounting the temp VC engine in any poise bot
+// The host keeps its own Data type and hands us a Voicers through VoicersData
+
+// Everything the engine's commands and events need, owned by whatever bot mounts it
+pub struct Voicers {
+    pub pool: Arc<SqlitePool>,
+    pub api: Arc<dyn DiscordApi>,
+    pub clock: Arc<dyn Clock>,
+    pub config: &'static Config,
+    pub health: Arc<Health>,
+    pub shutdown: CancellationToken,
+    pub supervisor: Arc<Supervisor>,
+    pub knocks: PendingKnocks,
+}
+
+impl Voicers {
+    pub fn new(
+        pool: Arc<SqlitePool>,
+        api: Arc<dyn DiscordApi>,
+        config: &'static Config,
+        health: Arc<Health>,
+        shutdown: CancellationToken,
+    ) -> Self {
+        Voicers {
+            pool,
+            api,
+            clock: Arc::new(SystemClock),
+            config,
+            supervisor: Arc::new(Supervisor::new(health.clone(), shutdown.clone())),
+            health,
+            shutdown,
+            knocks: PendingKnocks::default(),
+        }
+    }
+}
+
+// Implemented by the host bot's Data so our commands can find the engine state in it
+pub trait VoicersData: Send + Sync + 'static {
+    fn voicers(&self) -> &Voicers;
+
+    fn pool(&self) -> &SqlitePool {
+        &self.voicers().pool
+    }
+
+    fn config(&self) -> &'static Config {
+        self.voicers().config
+    }
+}
+
+impl VoicersData for Voicers {
+    fn voicers(&self) -> &Voicers {
+        self
+    }
+}
+
+pub type Context<'a, D> = poise::Context<'a, D, VoicersError>;
+
+// The temp VC commands, leaving out anything disabled in the config
+// Disabled commands are never registered so they don't show up in Discord at all
+pub fn commands<D: VoicersData>(config: &Config) -> Vec<poise::Command<D, VoicersError>> {
+    let mut commands = Vec::new();
+
+    if config.is_feature_enabled(Feature::NameTemplate) {
+        commands.push(commands::nametemplate::nametemplate());
+    }
+
+    // /vc only shows the subcommands that are left, and goes away with the last of them
+    let mut vc = commands::vc::vc();
+    vc.subcommands.retain(|subcommand| {
+        let feature = match subcommand.name.as_str() {
+            "rename" => Feature::VcRename,
+            "preset" => Feature::VcPresets,
+            "knock" => Feature::VcKnock,
+            _ => return true,
+        };
+        config.is_feature_enabled(feature)
+    });
+    if !vc.subcommands.is_empty() {
+        commands.push(vc);
+    }
+
+    if config.is_feature_enabled(Feature::CreateVcPrivate)
+        || config.is_feature_enabled(Feature::CreateVcPublic)
+    {
+        commands.push(commands::createvc::entrance());
+    }
+
+    commands
+}
+
+// Call this from the host's event_handler with every event
+// Ready starts the reaper, voice state updates keep the channel counts up to date
+pub async fn handle_event<D: VoicersData>(
+    event: &serenity::FullEvent,
+    data: &D,
+) -> Result<(), VoicersError> {
+    let voicers = data.voicers();
+    match event {
+        serenity::FullEvent::Ready { data_about_bot, .. } => {
+            println!("Logged in as {}", data_about_bot.user.name);
+            voicers.health.set_gateway_connected(true);
+
+            start_reaper(voicers);
+        }
+
+        serenity::FullEvent::Resume { .. } => {
+            voicers.health.set_gateway_connected(true);
+        }
+
+        // Serenity reports every reconnect here, so this is what notices a dropped gateway
+        serenity::FullEvent::ShardStageUpdate { event } => {
+            debug!("Shard stage update: {:?} -> {:?}", event.old, event.new);
+            voicers
+                .health
+                .set_gateway_connected(event.new == serenity::ConnectionStage::Connected);
+        }
+
+        serenity::FullEvent::VoiceStateUpdate { old, new } => {
+            debug!("Voice state update: {:?} -> {:?}", old, new);
+            voice_state_update(
+                voicers,
+                new.guild_id,
+                new.user_id,
+                old.as_ref().and_then(|old| old.channel_id),
+                new.channel_id,
+            )
+            .await?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// Start the reaper and its delete handler under the supervisor
+// Ready fires again after every reconnect, the supervisor makes sure we only start once
+pub fn start_reaper(voicers: &Voicers) {
+    if !voicers.config.is_feature_enabled(Feature::Reaper) {
+        info!("The reaper is disabled, voice channels will not be cleaned up");
+        return;
+    }
+
+    let (sender, receiver) = tokio::sync::mpsc::channel(100);
+    // Shared so a restarted delete handler picks up where the last one left off
+    let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+
+    // Start the polling task on ready
+    let pool = voicers.pool.clone();
+    let api = voicers.api.clone();
+    let clock = voicers.clock.clone();
+    let health = voicers.health.clone();
+    let shutdown = voicers.shutdown.clone();
+    let settings = ReaperSettings::from_config(voicers.config);
+    voicers.supervisor.spawn("reaper", move || {
+        reaper::start_polling(
+            pool.clone(),
+            sender.clone(),
+            api.clone(),
+            clock.clone(),
+            health.clone(),
+            settings.clone(),
+            shutdown.clone(),
+        )
+    });
+
+    // Runs until the poller drops its sender, so anything already queued still gets deleted
+    let api = voicers.api.clone();
+    let pool = voicers.pool.clone();
+    voicers.supervisor.spawn("reaper_delete", move || {
+        reaper::handle_polling_delete_event(receiver.clone(), api.clone(), pool.clone())
+    });
+}
+
+// A member joined, left or moved, keeps the counts up to date and sends the rules
+pub async fn voice_state_update(
+    voicers: &Voicers,
+    guild_id: Option<serenity::GuildId>,
+    user_id: serenity::UserId,
+    old_channel: Option<serenity::ChannelId>,
+    new_channel: Option<serenity::ChannelId>,
+) -> Result<(), VoicersError> {
+    // Get the Misc confit
+    let vcmisc_config = &voicers.config.misc;
+
+    // Handle voice state updates
+    metrics::VOICE_STATE_EVENTS.inc();
+    let now = voicers.clock.now();
+
+    // Prepare the rules for whoever just joined, sent to the VC's text chat
+    let rules_message = voicers
+        .config
+        .is_feature_enabled(Feature::RulesMessage)
+        .then(|| {
+            format!("<@{}> \n{} The mods will have direct access to this channel \n Please be sure to follow all the rules and guidelines of the server {} \n{}", user_id, vcmisc_config.vc_custom_prefix, vcmisc_config.vc_rules, vcmisc_config.vc_custom_suffix)
+        });
+
+    lifecycle::handle_voice_state(
+        voicers.api.as_ref(),
+        &voicers.pool,
+        guild_id,
+        user_id,
+        old_channel,
+        new_channel,
+        rules_message.as_deref(),
+        now,
+    )
+    .await
+}
+
+// Anything a command returns as Err ends up here
+// The user gets the friendly version of the error, the logs get the full one
+pub async fn on_error<D: VoicersData>(error: poise::FrameworkError<'_, D, VoicersError>) {
+    match error {
+        poise::FrameworkError::Command { error, ctx, .. } => {
+            error!(
+                command = ctx.command().qualified_name,
+                error = %error,
+                "Command failed"
+            );
+            reply_with_error(ctx, &error).await;
+        }
+        poise::FrameworkError::CommandCheckFailed {
+            error: Some(error),
+            ctx,
+            ..
+        } => {
+            error!(
+                command = ctx.command().qualified_name,
+                error = %error,
+                "Command check failed"
+            );
+            reply_with_error(ctx, &error).await;
+        }
+        other => {
+            if let Err(e) = poise::builtins::on_error(other).await {
+                error!("Error while handling error: {}", e);
+            }
+        }
+    }
+}
+
+async fn reply_with_error<D: VoicersData>(ctx: Context<'_, D>, error: &VoicersError) {
+    let reply = poise::CreateReply::default()
+        .content(error.user_message())
+        .ephemeral(true);
+    if let Err(e) = ctx.send(reply).await {
+        error!("Failed to tell the user about an error: {}", e);
+    }
+}
@@ -0,0 +1,169 @@
+use poise::serenity_prelude as serenity;
+use thiserror::Error;
+
+// Everything that can go wrong in VoiceRS
+// Display is for the logs, user_message() is what ends up in front of the person who ran the command
+#[derive(Debug, Error)]
+pub enum VoicersError {
+    #[error("Config error: {0}")]
+    Config(String),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    // Boxed since serenity's error is huge and every Result<_, VoicersError> would pay for it
+    #[error("Discord error: {0}")]
+    Discord(Box<serenity::Error>),
+    #[error("Permission denied: {0}")]
+    Permission(String),
+    #[error("Validation error: {0}")]
+    Validation(String),
+    #[error("Not found: {0}")]
+    NotFound(String),
+}
+
+impl From<serenity::Error> for VoicersError {
+    fn from(error: serenity::Error) -> Self {
+        VoicersError::Discord(Box::new(error))
+    }
+}
+
+impl VoicersError {
+    pub fn user_message(&self) -> String {
+        match self {
+            VoicersError::Config(_) => {
+                "The bot is not configured correctly, please let a moderator know".to_string()
+            }
+            VoicersError::Database(_) => {
+                "Something went wrong on our end, please try again in a moment".to_string()
+            }
+            VoicersError::Discord(e) => discord_user_message(e),
+            VoicersError::Permission(message)
+            | VoicersError::Validation(message)
+            | VoicersError::NotFound(message) => message.clone(),
+        }
+    }
+}
+
+// The JSON error code Discord sent back, if this was a rejected HTTP request
+pub fn discord_error_code(error: &serenity::Error) -> Option<isize> {
+    match error {
+        serenity::Error::Http(serenity::HttpError::UnsuccessfulRequest(response)) => {
+            Some(response.error.code)
+        }
+        _ => None,
+    }
+}
+
+// Validation errors on a single field come back as 50035 with the details per field
+fn invalid_field(error: &serenity::Error, field: &str) -> Option<String> {
+    match error {
+        serenity::Error::Http(serenity::HttpError::UnsuccessfulRequest(response)) => response
+            .error
+            .errors
+            .iter()
+            .find(|single| single.path == field)
+            .map(|single| single.code.clone()),
+        _ => None,
+    }
+}
+
+// https://discord.com/developers/docs/topics/opcodes-and-status-codes#json-json-error-codes
+fn discord_user_message(error: &serenity::Error) -> String {
+    if let Some(code) = invalid_field(error, "parent_id") {
+        return if code == "CHANNEL_PARENT_MAX_CHANNELS" {
+            "The voice channel category is full, please try again once some channels have closed"
+                .to_string()
+        } else {
+            "The voice channel category is missing or invalid, please let a moderator know"
+                .to_string()
+        };
+    }
+
+    match discord_error_code(error) {
+        Some(30013) => "This server has reached Discord's channel limit".to_string(),
+        Some(50013) | Some(50001) => {
+            "I don't have the Discord permissions to do that, please let a moderator know"
+                .to_string()
+        }
+        Some(10003) => "That channel doesn't exist anymore".to_string(),
+        Some(10007) | Some(10013) => "I couldn't find that member".to_string(),
+        _ => "Discord rejected the request, please try again in a moment".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What serenity hands us when Discord answers with a JSON error
+    async fn rejected(body: &str) -> VoicersError {
+        let response = http::Response::builder()
+            .status(400)
+            .body(body.to_string())
+            .unwrap();
+        let response =
+            serenity::ErrorResponse::from_response(response.into(), http::Method::POST).await;
+        serenity::Error::Http(serenity::HttpError::UnsuccessfulRequest(response)).into()
+    }
+
+    // Nothing from the internal error's Display may reach the user
+    fn assert_hides_details(error: &VoicersError, details: &str) {
+        assert!(error.to_string().contains(details));
+        assert!(!error.user_message().contains(details));
+    }
+
+    #[test]
+    fn config_errors_hide_the_details() {
+        let error = VoicersError::Config("bot_token_file /run/secrets/token".to_string());
+        assert_hides_details(&error, "/run/secrets/token");
+    }
+
+    #[test]
+    fn database_errors_hide_the_details() {
+        let error =
+            VoicersError::Database(sqlx::Error::Protocol("no such table: users".to_string()));
+        assert_hides_details(&error, "no such table");
+    }
+
+    #[test]
+    fn unknown_discord_errors_hide_the_details() {
+        let error = VoicersError::from(serenity::Error::Other("shard 3 is gone"));
+        assert_hides_details(&error, "shard 3");
+        assert!(error
+            .user_message()
+            .contains("Discord rejected the request"));
+    }
+
+    #[tokio::test]
+    async fn discord_error_codes_get_their_own_messages() {
+        let missing_permissions =
+            rejected(r#"{"code": 50013, "message": "Missing Permissions"}"#).await;
+        assert_hides_details(&missing_permissions, "Missing Permissions");
+        assert!(missing_permissions
+            .user_message()
+            .contains("I don't have the Discord permissions"));
+
+        let full_category = rejected(
+            r#"{"code": 50035, "message": "Invalid Form Body", "errors": {"parent_id": {"_errors": [{"code": "CHANNEL_PARENT_MAX_CHANNELS", "message": "Maximum number of channels in category reached (50)"}]}}}"#,
+        )
+        .await;
+        assert_hides_details(&full_category, "Invalid Form Body");
+        assert!(full_category.user_message().contains("category is full"));
+    }
+
+    #[test]
+    fn user_facing_messages_pass_through_unchanged() {
+        let message = "Only the owner of this voice channel can do that";
+        assert_eq!(
+            VoicersError::Permission(message.to_string()).user_message(),
+            message
+        );
+        assert_eq!(
+            VoicersError::Validation(message.to_string()).user_message(),
+            message
+        );
+        assert_eq!(
+            VoicersError::NotFound(message.to_string()).user_message(),
+            message
+        );
+    }
+}
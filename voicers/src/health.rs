@@ -0,0 +1,94 @@
+use crate::clock::{Clock, SystemClock};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Shared between the Discord event handler, the polling task and the HTTP server
+// The hot paths are atomics, only the supervisor takes the task lock and only on status changes
+pub struct Health {
+    clock: Arc<dyn Clock>,
+    started_at: i64,
+    gateway_connected: AtomicBool,
+    last_poll: AtomicI64,
+    tasks: Mutex<BTreeMap<&'static str, TaskReport>>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskStatus {
+    Running,
+    Restarting,
+    Stopped,
+}
+
+// What the supervisor last said about one of its tasks
+#[derive(Serialize, Clone, Debug)]
+pub struct TaskReport {
+    pub status: TaskStatus,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    pub since: i64,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Health::with_clock(Arc::new(SystemClock))
+    }
+
+    // Pass the same clock the lifecycle uses so the timestamps in reports line up
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Health {
+            started_at: clock.now(),
+            clock,
+            gateway_connected: AtomicBool::new(false),
+            last_poll: AtomicI64::new(0),
+            tasks: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn now(&self) -> i64 {
+        self.clock.now()
+    }
+
+    pub fn set_task(&self, name: &'static str, report: TaskReport) {
+        self.tasks.lock().unwrap().insert(name, report);
+    }
+
+    pub fn tasks(&self) -> BTreeMap<&'static str, TaskReport> {
+        self.tasks.lock().unwrap().clone()
+    }
+
+    pub fn set_gateway_connected(&self, connected: bool) {
+        self.gateway_connected.store(connected, Ordering::Relaxed);
+    }
+
+    pub fn gateway_connected(&self) -> bool {
+        self.gateway_connected.load(Ordering::Relaxed)
+    }
+
+    // Called at the end of every successful start_polling iteration
+    pub fn record_poll(&self, now: i64) {
+        self.last_poll.store(now, Ordering::Relaxed);
+    }
+
+    pub fn last_poll(&self) -> Option<i64> {
+        match self.last_poll.load(Ordering::Relaxed) {
+            0 => None,
+            last_poll => Some(last_poll),
+        }
+    }
+
+    // The polling task sleeps global_timeout between iterations, so give it a few of those
+    // before calling it dead. Before the first iteration we measure from startup instead
+    pub fn reaper_stalled(&self, now: i64, voice_timeout: u64) -> bool {
+        let last_seen = self.last_poll().unwrap_or(self.started_at);
+        now - last_seen > 3 * voice_timeout as i64
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health::new()
+    }
+}
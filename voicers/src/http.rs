@@ -0,0 +1,210 @@
+use crate::config::{Config, Feature};
+use crate::health::{Health, TaskReport, TaskStatus};
+use crate::metrics;
+use axum::{
+    extract::State, http::header, http::StatusCode, response::IntoResponse, routing::get, Json,
+    Router,
+};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+#[derive(Clone)]
+struct AppState {
+    pool: Arc<SqlitePool>,
+    health: Arc<Health>,
+    // None when the reaper is turned off
+    reaper_timeout: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct HealthReport {
+    gateway_connected: bool,
+    database_reachable: bool,
+    last_poll: Option<i64>,
+    reaper_stalled: bool,
+    tasks: BTreeMap<&'static str, TaskReport>,
+}
+
+// Serve the HTTP endpoints until the listener dies
+pub async fn serve(
+    config: &Config,
+    pool: Arc<SqlitePool>,
+    health: Arc<Health>,
+    shutdown: CancellationToken,
+) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
+        .with_state(AppState {
+            pool,
+            health,
+            // A disabled reaper never polls, which is not a failure
+            reaper_timeout: config
+                .is_feature_enabled(Feature::Reaper)
+                .then_some(config.voice.global_timeout),
+        });
+
+    let listener = tokio::net::TcpListener::bind(&config.http.bind_address).await?;
+    info!("HTTP server listening on {}", config.http.bind_address);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+}
+
+async fn metrics_handler() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        metrics::gather(),
+    )
+}
+
+// Liveness: only fails when the reaper stopped reporting, restarting the process is the fix for that
+async fn healthz_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let report = build_report(&state).await;
+    (liveness(&report), Json(report))
+}
+
+// Readiness: connected to Discord, the database answers and the background tasks are alive
+async fn readyz_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let report = build_report(&state).await;
+    (readiness(&report), Json(report))
+}
+
+fn liveness(report: &HealthReport) -> StatusCode {
+    if report.reaper_stalled {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    }
+}
+
+fn readiness(report: &HealthReport) -> StatusCode {
+    let tasks_running = report
+        .tasks
+        .values()
+        .all(|task| task.status == TaskStatus::Running);
+    let ready = report.gateway_connected
+        && report.database_reachable
+        && !report.reaper_stalled
+        && tasks_running;
+    if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+async fn build_report(state: &AppState) -> HealthReport {
+    let database_reachable = sqlx::query("SELECT 1").execute(&*state.pool).await.is_ok();
+    let reaper_stalled = state
+        .reaper_timeout
+        .is_some_and(|timeout| state.health.reaper_stalled(state.health.now(), timeout));
+
+    HealthReport {
+        gateway_connected: state.health.gateway_connected(),
+        database_reachable,
+        last_poll: state.health.last_poll(),
+        reaper_stalled,
+        tasks: state.health.tasks(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{busy, test_pool, PausedClock};
+    use std::time::Duration;
+
+    const TIMEOUT: u64 = 60;
+
+    // Connected, polled and with a working database, each test breaks one of those
+    async fn healthy() -> AppState {
+        let pool = test_pool(false).await;
+        tokio::time::pause();
+        let health = Health::with_clock(Arc::new(PausedClock::new()));
+        health.set_gateway_connected(true);
+        health.record_poll(health.now());
+        AppState {
+            pool: Arc::new(pool),
+            health: Arc::new(health),
+            reaper_timeout: Some(TIMEOUT),
+        }
+    }
+
+    #[tokio::test]
+    async fn healthy_is_live_and_ready() {
+        let state = healthy().await;
+        let report = busy(build_report(&state)).await;
+        assert!(report.database_reachable);
+        assert_eq!(liveness(&report), StatusCode::OK);
+        assert_eq!(readiness(&report), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn database_down_is_live_but_not_ready() {
+        let state = healthy().await;
+        state.pool.close().await;
+        let report = busy(build_report(&state)).await;
+        assert!(!report.database_reachable);
+        assert_eq!(liveness(&report), StatusCode::OK);
+        assert_eq!(readiness(&report), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn shard_disconnected_is_live_but_not_ready() {
+        let state = healthy().await;
+        state.health.set_gateway_connected(false);
+        let report = busy(build_report(&state)).await;
+        assert_eq!(liveness(&report), StatusCode::OK);
+        assert_eq!(readiness(&report), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn reaper_stalls_after_three_timeouts() {
+        let state = healthy().await;
+
+        tokio::time::advance(Duration::from_secs(3 * TIMEOUT)).await;
+        let report = busy(build_report(&state)).await;
+        assert!(!report.reaper_stalled);
+        assert_eq!(liveness(&report), StatusCode::OK);
+        assert_eq!(readiness(&report), StatusCode::OK);
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        let report = busy(build_report(&state)).await;
+        assert!(report.reaper_stalled);
+        assert_eq!(liveness(&report), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(readiness(&report), StatusCode::SERVICE_UNAVAILABLE);
+
+        // Nobody expects polls from a disabled reaper
+        let state = AppState {
+            reaper_timeout: None,
+            ..state
+        };
+        let report = busy(build_report(&state)).await;
+        assert!(!report.reaper_stalled);
+        assert_eq!(readiness(&report), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn a_stopped_task_is_not_ready() {
+        let state = healthy().await;
+        state.health.set_task(
+            "reaper",
+            TaskReport {
+                status: TaskStatus::Restarting,
+                restarts: 1,
+                last_error: Some("boom".to_string()),
+                since: 0,
+            },
+        );
+        let report = busy(build_report(&state)).await;
+        assert_eq!(liveness(&report), StatusCode::OK);
+        assert_eq!(readiness(&report), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
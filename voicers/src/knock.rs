@@ -0,0 +1,415 @@
+use crate::discord_api::{DiscordApi, KnockRequest};
+use crate::error::VoicersError;
+use crate::lifecycle;
+use poise::serenity_prelude::{self as serenity, Mentionable};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// /vc knock: someone asks the owner of a private VC to let them in
+
+#[derive(Clone, Copy, Debug)]
+pub struct Knock {
+    pub guild_id: serenity::GuildId,
+    pub channel_id: serenity::ChannelId,
+    pub owner_id: serenity::UserId,
+    pub knocker_id: serenity::UserId,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KnockOutcome {
+    Approved,
+    Denied,
+    Expired,
+    // The owner said yes but the knocker lost their mandatory role while waiting
+    NotAllowed,
+}
+
+impl Knock {
+    fn text(&self) -> String {
+        format!(
+            "{} is knocking on {}",
+            self.knocker_id.mention(),
+            self.channel_id.mention()
+        )
+    }
+
+    // Same rule as being invited on /createvc
+    pub async fn allowed(
+        &self,
+        api: &dyn DiscordApi,
+        mandatory_roles: &[String],
+    ) -> Result<bool, VoicersError> {
+        let member = api.fetch_member(self.guild_id, self.knocker_id).await?;
+        Ok(lifecycle::has_mandatory_role(
+            &member.roles,
+            mandatory_roles,
+        ))
+    }
+
+    pub async fn send(&self, api: &dyn DiscordApi) -> Result<KnockRequest, VoicersError> {
+        api.send_knock(self.owner_id, self.channel_id, &self.text())
+            .await
+            .map_err(|why| {
+                warn!(
+                    guild_id = self.guild_id.get(),
+                    vc_id = self.channel_id.get(),
+                    error = ?why,
+                    "Couldn't reach the owner of a voice channel for a knock"
+                );
+                VoicersError::Validation(
+                    "Couldn't reach the owner of that voice channel, try asking them directly"
+                        .to_string(),
+                )
+            })
+    }
+
+    // Wait for the owner to answer and act on it
+    // Anyone who can see the request can press its buttons, only the owner's presses count
+    pub async fn answer(
+        &self,
+        api: &dyn DiscordApi,
+        request: &KnockRequest,
+        mandatory_roles: &[String],
+        timeout: Duration,
+    ) -> Result<KnockOutcome, VoicersError> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let approved = loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            match api.next_knock_press(request, remaining).await {
+                Some(press) if press.user_id == self.owner_id => break press.approved,
+                Some(_) => continue,
+                None => {
+                    self.close(api, request, &format!("{} (expired)", self.text()))
+                        .await;
+                    return Ok(KnockOutcome::Expired);
+                }
+            }
+        };
+
+        if !approved {
+            let text = format!(
+                "Kept {} out of {}",
+                self.knocker_id.mention(),
+                self.channel_id.mention()
+            );
+            self.close(api, request, &text).await;
+            return Ok(KnockOutcome::Denied);
+        }
+
+        // Roles can change while the owner thinks about it
+        if !self.allowed(api, mandatory_roles).await? {
+            let text = format!(
+                "{} can't join private voice channels anymore, so they weren't let in",
+                self.knocker_id.mention()
+            );
+            self.close(api, request, &text).await;
+            return Ok(KnockOutcome::NotAllowed);
+        }
+
+        let granted = lifecycle::grant_access(
+            api,
+            self.guild_id,
+            self.channel_id,
+            self.knocker_id,
+            &format!("Knock approved by {}", self.owner_id),
+        )
+        .await;
+        if let Err(e) = granted {
+            let text = format!(
+                "Couldn't let {} in, try inviting them yourself",
+                self.knocker_id.mention()
+            );
+            self.close(api, request, &text).await;
+            return Err(e);
+        }
+
+        // Already sitting in another VC, so they don't have to go looking for it
+        if api
+            .member_voice_channel(self.guild_id, self.knocker_id)
+            .is_some()
+        {
+            if let Err(why) = api
+                .move_member(self.guild_id, self.knocker_id, self.channel_id)
+                .await
+            {
+                warn!(error = ?why, "Failed to move an approved knocker into the VC");
+            }
+        }
+
+        info!(
+            guild_id = self.guild_id.get(),
+            vc_id = self.channel_id.get(),
+            user_id = self.knocker_id.get(),
+            "Knock approved"
+        );
+        let text = format!(
+            "Let {} into {}",
+            self.knocker_id.mention(),
+            self.channel_id.mention()
+        );
+        self.close(api, request, &text).await;
+        Ok(KnockOutcome::Approved)
+    }
+
+    // The answer already happened, a request left with its buttons is only cosmetic
+    async fn close(&self, api: &dyn DiscordApi, request: &KnockRequest, text: &str) {
+        if let Err(why) = api.close_knock(request, text).await {
+            warn!(error = ?why, "Failed to update a knock request");
+        }
+    }
+}
+
+// Knocks still waiting on an owner, so nobody can knock on the same VC twice at once
+#[derive(Default)]
+pub struct PendingKnocks {
+    pending: Mutex<HashSet<(serenity::UserId, serenity::ChannelId)>>,
+}
+
+impl PendingKnocks {
+    // None when the knocker already has a knock on this VC waiting
+    // Otherwise it stays pending until the returned guard is dropped, errors and panics included
+    pub fn start(&self, knock: &Knock) -> Option<PendingKnock<'_>> {
+        let key = (knock.knocker_id, knock.channel_id);
+        // Not then_some, a guard built for a refused knock would remove the one that's waiting
+        if !self.pending.lock().unwrap().insert(key) {
+            return None;
+        }
+        Some(PendingKnock { knocks: self, key })
+    }
+}
+
+pub struct PendingKnock<'a> {
+    knocks: &'a PendingKnocks,
+    key: (serenity::UserId, serenity::ChannelId),
+}
+
+impl Drop for PendingKnock<'_> {
+    fn drop(&mut self) {
+        self.knocks.pending.lock().unwrap().remove(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discord_api::{ChannelInfo, KnockPress, VoiceSettings};
+    use crate::testing::{FakeGuild, BOT, GUILD};
+
+    const ROLE: u64 = 7;
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    struct Setup {
+        guild: FakeGuild,
+        knock: Knock,
+        mandatory_roles: Vec<String>,
+    }
+
+    // A private VC owned by 10, with 11 knocking on it
+    fn setup() -> Setup {
+        let guild = FakeGuild::new();
+        let owner_id = guild.add_member(10, "owner", &[ROLE]);
+        let knocker_id = guild.add_member(11, "knocker", &[ROLE]);
+        let overwrite = |allow, deny, kind| serenity::PermissionOverwrite { allow, deny, kind };
+        let view = serenity::Permissions::VIEW_CHANNEL;
+        let none = serenity::Permissions::empty();
+        let channel_id = serenity::ChannelId::new(100);
+        guild.add_channel(ChannelInfo {
+            id: channel_id,
+            name: "secret club".to_string(),
+            kind: serenity::ChannelType::Voice,
+            parent_id: None,
+            permission_overwrites: vec![
+                overwrite(
+                    none,
+                    view,
+                    serenity::PermissionOverwriteType::Role(GUILD.everyone_role()),
+                ),
+                overwrite(
+                    view,
+                    none,
+                    serenity::PermissionOverwriteType::Member(owner_id),
+                ),
+                overwrite(
+                    view | serenity::Permissions::MANAGE_CHANNELS,
+                    none,
+                    serenity::PermissionOverwriteType::Member(BOT),
+                ),
+            ],
+            settings: VoiceSettings::default(),
+        });
+
+        Setup {
+            guild,
+            knock: Knock {
+                guild_id: GUILD,
+                channel_id,
+                owner_id,
+                knocker_id,
+            },
+            mandatory_roles: vec![ROLE.to_string()],
+        }
+    }
+
+    impl Setup {
+        fn press(&self, user_id: serenity::UserId, approved: bool) {
+            self.guild
+                .state()
+                .knock_presses
+                .push_back(KnockPress { user_id, approved });
+        }
+
+        async fn run(&self) -> Result<KnockOutcome, VoicersError> {
+            let request = self.knock.send(&self.guild).await?;
+            self.knock
+                .answer(&self.guild, &request, &self.mandatory_roles, TIMEOUT)
+                .await
+        }
+
+        fn let_in(&self) -> bool {
+            let member = serenity::PermissionOverwriteType::Member(self.knock.knocker_id);
+            self.guild.state().channels[&self.knock.channel_id]
+                .permission_overwrites
+                .iter()
+                .any(|overwrite| overwrite.kind == member && overwrite.allow.view_channel())
+        }
+
+        fn request_text(&self) -> String {
+            self.guild.state().knocks[0].1.clone()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn approving_lets_the_knocker_in() {
+        let setup = setup();
+        setup.press(setup.knock.owner_id, true);
+
+        assert_eq!(setup.run().await.unwrap(), KnockOutcome::Approved);
+        assert!(setup.let_in());
+        assert!(setup.request_text().starts_with("Let "));
+        // The request went to the owner's DMs
+        assert_eq!(
+            setup.guild.state().knocks[0].0,
+            serenity::ChannelId::new(setup.knock.owner_id.get())
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn approving_moves_a_knocker_already_in_voice() {
+        let setup = setup();
+        let lobby = serenity::ChannelId::new(200);
+        setup.guild.join(setup.knock.knocker_id, lobby);
+        setup.press(setup.knock.owner_id, true);
+
+        assert_eq!(setup.run().await.unwrap(), KnockOutcome::Approved);
+        assert_eq!(
+            setup.guild.state().voice_states[&setup.knock.knocker_id],
+            setup.knock.channel_id
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn denying_keeps_the_knocker_out() {
+        let setup = setup();
+        setup.press(setup.knock.owner_id, false);
+
+        assert_eq!(setup.run().await.unwrap(), KnockOutcome::Denied);
+        assert!(!setup.let_in());
+        assert!(setup.request_text().starts_with("Kept "));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unanswered_knocks_expire_after_the_timeout() {
+        let setup = setup();
+        let started = tokio::time::Instant::now();
+
+        assert_eq!(setup.run().await.unwrap(), KnockOutcome::Expired);
+        assert_eq!(started.elapsed(), TIMEOUT);
+        assert!(!setup.let_in());
+        assert!(setup.request_text().ends_with("(expired)"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn only_the_owner_can_answer() {
+        let setup = setup();
+        let stranger = setup.guild.add_member(12, "stranger", &[ROLE]);
+        setup.press(stranger, true);
+        setup.press(setup.knock.knocker_id, true);
+
+        assert_eq!(setup.run().await.unwrap(), KnockOutcome::Expired);
+        assert!(!setup.let_in());
+
+        // And their presses don't use up the owner's
+        let setup = self::setup();
+        setup.press(stranger, true);
+        setup.press(setup.knock.owner_id, false);
+        assert_eq!(setup.run().await.unwrap(), KnockOutcome::Denied);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn knockers_who_lost_their_role_are_not_let_in() {
+        let setup = setup();
+        setup.press(setup.knock.owner_id, true);
+        setup
+            .guild
+            .state()
+            .members
+            .get_mut(&setup.knock.knocker_id)
+            .unwrap()
+            .roles
+            .clear();
+
+        assert_eq!(setup.run().await.unwrap(), KnockOutcome::NotAllowed);
+        assert!(!setup.let_in());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn owners_with_dms_closed_get_asked_in_the_vc() {
+        let setup = setup();
+        setup.guild.state().dms_closed = true;
+        setup.press(setup.knock.owner_id, false);
+
+        setup.run().await.unwrap();
+        assert_eq!(setup.guild.state().knocks[0].0, setup.knock.channel_id);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_failed_grant_still_closes_the_request() {
+        let setup = setup();
+        setup.press(setup.knock.owner_id, true);
+        let request = setup.knock.send(&setup.guild).await.unwrap();
+        // Deleted while the owner was deciding
+        setup.guild.state().channels.remove(&setup.knock.channel_id);
+
+        let answer = setup
+            .knock
+            .answer(&setup.guild, &request, &setup.mandatory_roles, TIMEOUT)
+            .await;
+        assert!(matches!(answer, Err(VoicersError::NotFound(_))));
+        assert!(setup.request_text().starts_with("Couldn't let "));
+    }
+
+    #[test]
+    fn one_pending_knock_per_knocker_and_channel() {
+        let knocks = PendingKnocks::default();
+        let knock = Knock {
+            guild_id: GUILD,
+            channel_id: serenity::ChannelId::new(100),
+            owner_id: serenity::UserId::new(10),
+            knocker_id: serenity::UserId::new(11),
+        };
+        let other_channel = Knock {
+            channel_id: serenity::ChannelId::new(101),
+            ..knock
+        };
+
+        let pending = knocks.start(&knock);
+        assert!(pending.is_some());
+        assert!(knocks.start(&knock).is_none());
+        assert!(knocks.start(&other_channel).is_some());
+
+        drop(pending);
+        assert!(knocks.start(&knock).is_some());
+    }
+}
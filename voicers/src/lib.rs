@@ -0,0 +1,25 @@
+// The temp VC engine as a library, so it can be embedded in another poise bot
+// main.rs is just one way of wiring these together
+pub mod clock;
+pub mod commands;
+pub mod config;
+pub mod discord;
+pub mod discord_api;
+pub mod engine;
+pub mod error;
+pub mod health;
+pub mod http;
+pub mod knock;
+pub mod lifecycle;
+pub mod logging;
+pub mod metrics;
+pub mod naming;
+pub mod presets;
+pub mod reaper;
+pub mod shutdown;
+pub mod storage;
+pub mod supervisor;
+// Fakes for testing code built on top of the engine, without Discord
+// Behind the testing feature so they stay out of release builds
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
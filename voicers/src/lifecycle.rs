@@ -0,0 +1,1420 @@
+use crate::clock::Clock;
+use crate::config::{Config, Feature, OrphanPolicy, Voice};
+use crate::discord_api::{
+    self, ChannelEdit, ChannelInfo, DiscordApi, NewVoiceChannel, VoiceSettings,
+};
+use crate::error::VoicersError;
+use crate::storage::{self, User};
+use crate::{metrics, naming};
+use poise::serenity_prelude as serenity;
+use sqlx::SqlitePool;
+use tracing::{debug, error, info, warn};
+
+// Create a temp VC and start tracking it, as one step from the bot's point of view
+// If Discord refuses nothing is written, and if the database refuses the channel is deleted again
+// so the reaper never ends up with a channel it doesn't know about
+pub async fn create_tracked_channel(
+    api: &dyn DiscordApi,
+    pool: &SqlitePool,
+    guild_id: serenity::GuildId,
+    owner_id: Option<serenity::UserId>,
+    channel: NewVoiceChannel,
+    now: i64,
+) -> Result<serenity::ChannelId, VoicersError> {
+    let vc_type = if hides_from_everyone(&channel.permissions, guild_id) {
+        "private"
+    } else {
+        "public"
+    };
+    let channel_id = api.create_voice_channel(guild_id, channel).await?;
+
+    if let Err(e) = storage::insert_channel(
+        pool,
+        channel_id.get() as i64,
+        guild_id.get() as i64,
+        owner_id.map(|owner_id| owner_id.get() as i64),
+        now,
+    )
+    .await
+    {
+        error!(
+            guild_id = guild_id.get(),
+            vc_id = channel_id.get(),
+            error = ?e,
+            "Failed to track new voice channel, deleting it again"
+        );
+        if let Err(delete_error) = api
+            .delete_channel(channel_id, "Rolling back untracked temporary channel")
+            .await
+        {
+            // Nothing more we can do here, at least leave a trail for whoever cleans up
+            warn!(
+                guild_id = guild_id.get(),
+                vc_id = channel_id.get(),
+                error = ?delete_error,
+                "Failed to roll back voice channel, it is now orphaned"
+            );
+        }
+        return Err(e.into());
+    }
+
+    metrics::CHANNELS_CREATED
+        .with_label_values(&[vc_type])
+        .inc();
+    Ok(channel_id)
+}
+
+// Every temp VC gets a member overwrite letting the bot manage it and an @everyone overwrite
+// for VIEW_CHANNEL (see create_voice_channel). A channel made by hand and synced to its category
+// has the category's overwrites instead, even when the category lets the bot manage channels
+// (the auto created one does), so a synced channel is never ours
+pub fn is_bot_created(
+    channel: &ChannelInfo,
+    parent: Option<&ChannelInfo>,
+    guild_id: serenity::GuildId,
+    bot_user_id: serenity::UserId,
+) -> bool {
+    let overwrites = &channel.permission_overwrites;
+    let bot_manages = overwrites.iter().any(|overwrite| {
+        overwrite.kind == serenity::PermissionOverwriteType::Member(bot_user_id)
+            && overwrite
+                .allow
+                .contains(serenity::Permissions::MANAGE_CHANNELS)
+    });
+    let everyone = serenity::PermissionOverwriteType::Role(guild_id.everyone_role());
+    let sets_everyone = overwrites.iter().any(|overwrite| {
+        overwrite.kind == everyone && (overwrite.allow | overwrite.deny).view_channel()
+    });
+    let synced = parent.is_some_and(|parent| {
+        parent.permission_overwrites.len() == overwrites.len()
+            && overwrites
+                .iter()
+                .all(|overwrite| parent.permission_overwrites.contains(overwrite))
+    });
+
+    bot_manages && sets_everyone && !synced
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct OrphanReport {
+    pub adopted: usize,
+    pub deleted: usize,
+}
+
+// Find temp VCs in our categories that the database has forgotten about and deal with them per the policy
+// Adopted channels are tracked as empty as of now, the next sync fixes the count if people are in them
+pub async fn reconcile_orphans(
+    api: &dyn DiscordApi,
+    pool: &SqlitePool,
+    guild_id: serenity::GuildId,
+    categories: &[serenity::ChannelId],
+    bot_user_id: serenity::UserId,
+    policy: OrphanPolicy,
+    now: i64,
+) -> Result<OrphanReport, VoicersError> {
+    let mut report = OrphanReport::default();
+    if policy == OrphanPolicy::Ignore {
+        return Ok(report);
+    }
+
+    let tracked = storage::tracked_channel_ids(pool, guild_id.get() as i64).await?;
+    let channels = api.guild_channels(guild_id).await?;
+    let parent_of = |channel: &ChannelInfo| {
+        channels
+            .iter()
+            .find(|parent| Some(parent.id) == channel.parent_id)
+    };
+    let orphans: Vec<&ChannelInfo> = channels
+        .iter()
+        .filter(|channel| channel.kind == serenity::ChannelType::Voice)
+        .filter(|channel| {
+            channel
+                .parent_id
+                .is_some_and(|parent| categories.contains(&parent))
+        })
+        .filter(|channel| is_bot_created(channel, parent_of(channel), guild_id, bot_user_id))
+        .filter(|channel| !tracked.contains(&(channel.id.get() as i64)))
+        .collect();
+
+    for orphan in orphans {
+        match policy {
+            OrphanPolicy::Adopt => {
+                storage::insert_channel(
+                    pool,
+                    orphan.id.get() as i64,
+                    guild_id.get() as i64,
+                    None,
+                    now,
+                )
+                .await?;
+                info!(
+                    guild_id = guild_id.get(),
+                    vc_id = orphan.id.get(),
+                    name = %orphan.name,
+                    "Adopted orphaned voice channel"
+                );
+                report.adopted += 1;
+            }
+            OrphanPolicy::Delete => {
+                api.delete_channel(orphan.id, "Cleaning up orphaned temporary channel")
+                    .await?;
+                metrics::CHANNELS_DELETED
+                    .with_label_values(&["orphaned"])
+                    .inc();
+                info!(
+                    guild_id = guild_id.get(),
+                    vc_id = orphan.id.get(),
+                    name = %orphan.name,
+                    "Deleted orphaned voice channel"
+                );
+                report.deleted += 1;
+            }
+            OrphanPolicy::Ignore => {}
+        }
+    }
+
+    Ok(report)
+}
+
+// A member's voice state changed, keep the counts of tracked channels in step
+// A move is a leave and a join, channels we don't track are simply not in the table
+// The rules message is sent to whatever channel they ended up in
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_voice_state(
+    api: &dyn DiscordApi,
+    pool: &SqlitePool,
+    guild_id: Option<serenity::GuildId>,
+    user_id: serenity::UserId,
+    old_channel: Option<serenity::ChannelId>,
+    new_channel: Option<serenity::ChannelId>,
+    rules_message: Option<&str>,
+    now: i64,
+) -> Result<(), VoicersError> {
+    let guild_id = guild_id.map(|id| id.get());
+
+    if let Some(channel_id) = new_channel {
+        info!(
+            guild_id,
+            vc_id = channel_id.get(),
+            user_id = user_id.get(),
+            "User joined a voice channel"
+        );
+
+        if let Some(message) = rules_message {
+            if let Err(e) = api.send_message(channel_id, message).await {
+                error!("Failed to send message to text channel: {:?}", e);
+            }
+        }
+
+        storage::user_joined(pool, channel_id.get() as i64, now)
+            .await
+            .map_err(|e| {
+                error!("Failed to update database: {:?}", e);
+                VoicersError::from(e)
+            })?;
+    }
+
+    if let Some(channel_id) = old_channel {
+        info!(
+            guild_id,
+            vc_id = channel_id.get(),
+            user_id = user_id.get(),
+            "User left a voice channel"
+        );
+
+        storage::user_left(pool, channel_id.get() as i64, now)
+            .await
+            .map_err(|e| {
+                error!("Failed to update database: {:?}", e);
+                VoicersError::from(e)
+            })?;
+    }
+
+    Ok(())
+}
+
+// Correct the tracked counts from the voice states Discord gave us, in case we missed an event
+// A guild we can't see right now (bot was kicked, outage) is skipped instead of failing the sync
+pub async fn sync_user_counts(api: &dyn DiscordApi, pool: &SqlitePool) -> Result<(), VoicersError> {
+    for row in storage::tracked_channels(pool).await? {
+        let channel_id = serenity::ChannelId::new(row.vc_id as u64);
+        let guild_id = serenity::GuildId::new(row.guild_id as u64);
+
+        let current_user_count = match api.voice_channel_user_count(guild_id, channel_id).await {
+            Ok(count) => count as i32,
+            Err(e) => {
+                warn!(guild_id = row.guild_id, vc_id = row.vc_id, error = %e, "Unable to sync VC");
+                continue;
+            }
+        };
+
+        info!(
+            guild_id = row.guild_id,
+            vc_id = row.vc_id,
+            user_count = current_user_count,
+            "Syncing VC with DB user count"
+        );
+
+        // Only update the database if the user count has changed
+        if current_user_count != row.user_count {
+            storage::set_user_count(pool, row.vc_id, current_user_count).await?;
+            info!(
+                guild_id = row.guild_id,
+                vc_id = row.vc_id,
+                user_count = current_user_count,
+                "Updated VC with new user count"
+            );
+        } else {
+            info!(
+                guild_id = row.guild_id,
+                vc_id = row.vc_id,
+                "No update needed for VC"
+            );
+        }
+    }
+    Ok(())
+}
+
+// How many reaper passes between syncs with Discord's voice states
+pub const SYNC_INTERVAL: u32 = 4;
+
+// Discord won't put more channels than this in one category
+pub const CATEGORY_CHANNEL_LIMIT: usize = 50;
+
+// Where new temp VCs go, read from the config
+#[derive(Clone, Debug, Default)]
+pub struct CategorySettings {
+    pub categories: Vec<serenity::ChannelId>,
+    pub auto_create: bool,
+    // Get to see the auto created category
+    pub moderator_roles: Vec<serenity::RoleId>,
+}
+
+impl CategorySettings {
+    pub fn from_config(config: &Config) -> Self {
+        CategorySettings {
+            categories: config
+                .misc
+                .vc_categories()
+                .into_iter()
+                .map(serenity::ChannelId::new)
+                .collect(),
+            auto_create: config.misc.vc_auto_category,
+            moderator_roles: config
+                .moderation
+                .moderator_roles
+                .iter()
+                .filter_map(|id| id.parse().ok())
+                .map(serenity::RoleId::new)
+                .collect(),
+        }
+    }
+}
+
+pub const AUTO_CATEGORY_NAME: &str = "Temp Voice";
+
+// The first of the guild's categories with room left
+// Configured categories that don't exist in this guild are skipped, and if none are left
+// it's the auto created category, or the top level (None) when that's turned off
+pub async fn choose_category(
+    api: &dyn DiscordApi,
+    pool: &SqlitePool,
+    guild_id: serenity::GuildId,
+    settings: &CategorySettings,
+) -> Result<Option<serenity::ChannelId>, VoicersError> {
+    if settings.categories.is_empty() && !settings.auto_create {
+        return Ok(None);
+    }
+
+    let channels = api.guild_channels(guild_id).await?;
+    let exists = |category: &serenity::ChannelId| {
+        channels.iter().any(|channel| {
+            channel.id == *category && channel.kind == serenity::ChannelType::Category
+        })
+    };
+
+    let mut categories: Vec<serenity::ChannelId> =
+        settings.categories.iter().copied().filter(exists).collect();
+    if categories.len() < settings.categories.len() {
+        debug!(
+            guild_id = guild_id.get(),
+            "Some configured categories don't exist in this guild"
+        );
+    }
+
+    if categories.is_empty() {
+        if !settings.auto_create {
+            warn!(
+                guild_id = guild_id.get(),
+                "None of the configured categories exist in this guild, creating at the top level"
+            );
+            return Ok(None);
+        }
+        let stored = storage::guild_category(pool, guild_id.get() as i64)
+            .await?
+            .map(|id| serenity::ChannelId::new(id as u64))
+            .filter(exists);
+        categories.push(match stored {
+            Some(category) => category,
+            None => create_category(api, pool, guild_id, settings).await?,
+        });
+    }
+
+    for category in &categories {
+        let used = channels
+            .iter()
+            .filter(|channel| channel.parent_id == Some(*category))
+            .count();
+        if used < CATEGORY_CHANNEL_LIMIT {
+            return Ok(Some(*category));
+        }
+        debug!(
+            category = category.get(),
+            "Category is full, trying the next one"
+        );
+    }
+
+    warn!(
+        guild_id = guild_id.get(),
+        "Every voice channel category is full"
+    );
+    Err(VoicersError::Validation(
+        "Every voice channel category is full, please try again once some channels have closed"
+            .to_string(),
+    ))
+}
+
+// Make the Temp Voice category and remember it for next time
+async fn create_category(
+    api: &dyn DiscordApi,
+    pool: &SqlitePool,
+    guild_id: serenity::GuildId,
+    settings: &CategorySettings,
+) -> Result<serenity::ChannelId, VoicersError> {
+    let visible = serenity::Permissions::VIEW_CHANNEL | serenity::Permissions::MANAGE_CHANNELS;
+    let mut permissions: Vec<serenity::PermissionOverwrite> = settings
+        .moderator_roles
+        .iter()
+        .map(|role_id| serenity::PermissionOverwrite {
+            allow: visible,
+            deny: serenity::Permissions::empty(),
+            kind: serenity::PermissionOverwriteType::Role(*role_id),
+        })
+        .collect();
+    permissions.push(serenity::PermissionOverwrite {
+        allow: visible,
+        deny: serenity::Permissions::empty(),
+        kind: serenity::PermissionOverwriteType::Member(api.current_user_id()),
+    });
+
+    let category = api
+        .create_category(
+            guild_id,
+            AUTO_CATEGORY_NAME,
+            permissions,
+            "Category for temporary voice channels",
+        )
+        .await?;
+    storage::set_guild_category(pool, guild_id.get() as i64, category.get() as i64).await?;
+    info!(
+        guild_id = guild_id.get(),
+        category = category.get(),
+        "Created a category for temporary voice channels"
+    );
+    Ok(category)
+}
+
+// The vctype argument, checked before anything else looks at it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VcType {
+    Private,
+    Public,
+}
+
+impl VcType {
+    pub fn parse(vctype: &str) -> Result<Self, VoicersError> {
+        //imagine forgetting to use to_lowercase() and having to debug for 2 hours
+        match vctype.trim().to_lowercase().as_str() {
+            "private" => Ok(VcType::Private),
+            "public" => Ok(VcType::Public),
+            _ => Err(VoicersError::Validation(format!(
+                "{} is not a voice channel type, use Private or Public",
+                vctype
+            ))),
+        }
+    }
+
+    // Used in channel names, metrics and logs
+    pub fn name(self) -> &'static str {
+        match self {
+            VcType::Private => "private",
+            VcType::Public => "public",
+        }
+    }
+
+    // Both types can be turned off separately in the config
+    pub fn feature(self) -> Feature {
+        match self {
+            VcType::Private => Feature::CreateVcPrivate,
+            VcType::Public => Feature::CreateVcPublic,
+        }
+    }
+}
+
+// What /createvc asks for once its arguments and the preset are sorted out
+pub struct CreateRequest {
+    pub vc_type: VcType,
+    // None builds one from the server's name template
+    pub name: Option<String>,
+    // Display name for the {owner} placeholder
+    pub owner_name: String,
+    // Already checked for the mandatory roles
+    pub invited_users: Vec<serenity::UserId>,
+    pub invited_roles: Vec<serenity::RoleId>,
+    pub settings: VoiceSettings,
+}
+
+// Everything /createvc does after parsing: the limits, the name, the overwrites,
+// the category and finally the tracked channel itself
+pub async fn create_vc(
+    api: &dyn DiscordApi,
+    pool: &SqlitePool,
+    config: &Config,
+    clock: &dyn Clock,
+    guild_id: serenity::GuildId,
+    owner_id: serenity::UserId,
+    request: CreateRequest,
+) -> Result<serenity::ChannelId, VoicersError> {
+    let now = clock.now();
+    reserve_create_slot(pool, &config.voice, guild_id, owner_id, now).await?;
+
+    // From here on the slot is ours, so every failure has to hand it back
+    let vc_type = request.vc_type;
+    let channel_id = match create_claimed(api, pool, config, guild_id, owner_id, request, now).await
+    {
+        Ok(channel_id) => channel_id,
+        Err(e) => {
+            if let Err(release_error) =
+                storage::release_create_slot(pool, guild_id.get() as i64, owner_id.get() as i64)
+                    .await
+            {
+                warn!(guild_id = guild_id.get(), error = %release_error, "Failed to release the create slot");
+            }
+            return Err(e);
+        }
+    };
+
+    // The channel is there either way, a stuck slot only lasts until the next restart
+    if let Err(e) = storage::finish_create(pool, guild_id.get() as i64, owner_id.get() as i64).await
+    {
+        error!(guild_id = guild_id.get(), error = %e, "Failed to finish the create slot");
+    }
+
+    info!(
+        guild_id = guild_id.get(),
+        vc_id = channel_id.get(),
+        owner_id = owner_id.get(),
+        vctype = vc_type.name(),
+        "Created voice channel"
+    );
+    Ok(channel_id)
+}
+
+// The part of create_vc that runs with the create slot claimed
+async fn create_claimed(
+    api: &dyn DiscordApi,
+    pool: &SqlitePool,
+    config: &Config,
+    guild_id: serenity::GuildId,
+    owner_id: serenity::UserId,
+    request: CreateRequest,
+    now: i64,
+) -> Result<serenity::ChannelId, VoicersError> {
+    let name = match &request.name {
+        Some(name) => name.clone(),
+        None => {
+            naming::channel_name(
+                api,
+                pool,
+                &config.voice,
+                guild_id,
+                owner_id,
+                &request.owner_name,
+                request.vc_type.name(),
+                now,
+            )
+            .await?
+        }
+    };
+    // Typed or generated, every name goes through the same filter
+    let name = naming::check_name(&name, &config.voice, guild_id, owner_id)?;
+    debug!("naming new VC as: {}", name);
+
+    let permissions =
+        channel_permissions(config, guild_id, owner_id, api.current_user_id(), &request);
+
+    // Whatever asked for it, Discord refuses more than the boost level allows
+    let mut settings = request.settings;
+    if let Some(bitrate) = settings.bitrate {
+        let clamped = discord_api::clamp_bitrate(bitrate, api.premium_tier(guild_id));
+        if clamped != bitrate {
+            debug!(
+                "Bitrate {} is past what the server allows, using {}",
+                bitrate, clamped
+            );
+        }
+        settings.bitrate = Some(clamped);
+    }
+
+    // The first category with room gets the channel, see choose_category for the fallbacks
+    let category =
+        choose_category(api, pool, guild_id, &CategorySettings::from_config(config)).await?;
+
+    debug!("Creating the channel");
+    let channel = NewVoiceChannel {
+        name,
+        category,
+        permissions,
+        settings,
+        audit_reason: "Bot created temporary channel".to_string(),
+    };
+    create_tracked_channel(api, pool, guild_id, Some(owner_id), channel, now)
+        .await
+        .inspect_err(|e| {
+            error!(guild_id = guild_id.get(), error = %e, "Failed to create voice channel");
+        })
+}
+
+// Invitees, moderators, @everyone by type, the owner and the bot itself
+fn channel_permissions(
+    config: &Config,
+    guild_id: serenity::GuildId,
+    owner_id: serenity::UserId,
+    bot_id: serenity::UserId,
+    request: &CreateRequest,
+) -> Vec<serenity::PermissionOverwrite> {
+    let overwrite = |allow, kind| serenity::PermissionOverwrite {
+        allow,
+        deny: serenity::Permissions::empty(),
+        kind,
+    };
+    let view = serenity::Permissions::VIEW_CHANNEL;
+    let mut permissions = Vec::new();
+
+    for user_id in &request.invited_users {
+        permissions.push(overwrite(
+            view,
+            serenity::PermissionOverwriteType::Member(*user_id),
+        ));
+    }
+    for role_id in &request.invited_roles {
+        permissions.push(overwrite(
+            view,
+            serenity::PermissionOverwriteType::Role(*role_id),
+        ));
+    }
+
+    // Moderator roles get access to every VC unless mod_overrides is disabled
+    if config.is_feature_enabled(Feature::ModOverrides) {
+        for role_id in config
+            .moderation
+            .moderator_roles
+            .iter()
+            .filter_map(|id| id.parse::<u64>().ok())
+        {
+            permissions.push(overwrite(
+                view | serenity::Permissions::MANAGE_CHANNELS,
+                serenity::PermissionOverwriteType::Role(serenity::RoleId::new(role_id)),
+            ));
+        }
+    }
+
+    let everyone = serenity::PermissionOverwriteType::Role(guild_id.everyone_role());
+    permissions.push(match request.vc_type {
+        VcType::Private => serenity::PermissionOverwrite {
+            allow: serenity::Permissions::empty(),
+            deny: view,
+            kind: everyone,
+        },
+        VcType::Public => overwrite(view, everyone),
+    });
+
+    permissions.push(overwrite(
+        view,
+        serenity::PermissionOverwriteType::Member(owner_id),
+    ));
+    permissions.push(overwrite(
+        view | serenity::Permissions::MANAGE_CHANNELS,
+        serenity::PermissionOverwriteType::Member(bot_id),
+    ));
+    permissions
+}
+
+// Claim the owner's create slot, or refuse when the owner or the guild already has too many,
+// or the owner just made one or is still making one. The error says how long to wait where waiting helps
+// Claiming and counting share a transaction so two creates at once can't both slip under a limit,
+// the claim has to be finished or released once the channel is made or not
+pub async fn reserve_create_slot(
+    pool: &SqlitePool,
+    voice: &Voice,
+    guild_id: serenity::GuildId,
+    owner_id: serenity::UserId,
+    now: i64,
+) -> Result<(), VoicersError> {
+    let guild = guild_id.get() as i64;
+    let owner = owner_id.get() as i64;
+    // Every early return drops the transaction, which rolls the claim back
+    let mut tx = pool.begin().await?;
+
+    let cooldown = voice.create_cooldown as i64;
+    if !storage::claim_create_slot(&mut tx, guild, owner, now, cooldown).await? {
+        let (last_created, creating) = storage::create_slot(&mut tx, guild, owner)
+            .await?
+            .unwrap_or_default();
+        if creating {
+            return Err(VoicersError::Validation(
+                "Your last voice channel is still being created, give it a moment".to_string(),
+            ));
+        }
+        return Err(VoicersError::Validation(format!(
+            "You just created a voice channel, please wait {} before creating another",
+            format_wait((last_created + cooldown - now).max(1))
+        )));
+    }
+
+    if voice.max_channels_per_user > 0
+        && storage::owned_channel_count(&mut tx, guild, owner).await?
+            >= voice.max_channels_per_user as i64
+    {
+        return Err(VoicersError::Validation(format!(
+            "You already have {} voice channels open, they close once they've been empty for {}",
+            voice.max_channels_per_user,
+            format_wait(voice.global_timeout as i64)
+        )));
+    }
+
+    // Creates still in flight count as open channels, this one included
+    if voice.max_channels_per_guild > 0
+        && storage::guild_channel_count(&mut tx, guild).await?
+            + storage::pending_creates(&mut tx, guild).await?
+            > voice.max_channels_per_guild as i64
+    {
+        return Err(VoicersError::Validation(
+            "This server has as many voice channels open as it allows, please join one of those or try again later"
+                .to_string(),
+        ));
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+fn format_wait(seconds: i64) -> String {
+    let unit = |count: i64, name: &str| {
+        if count == 1 {
+            format!("1 {}", name)
+        } else {
+            format!("{} {}s", count, name)
+        }
+    };
+    match (seconds / 60, seconds % 60) {
+        (0, seconds) => unit(seconds, "second"),
+        (minutes, 0) => unit(minutes, "minute"),
+        (minutes, seconds) => format!("{} {}", unit(minutes, "minute"), unit(seconds, "second")),
+    }
+}
+
+// Private VCs are the ones /createvc hid from @everyone
+pub fn is_private(channel: &ChannelInfo, guild_id: serenity::GuildId) -> bool {
+    hides_from_everyone(&channel.permission_overwrites, guild_id)
+}
+
+fn hides_from_everyone(
+    permissions: &[serenity::PermissionOverwrite],
+    guild_id: serenity::GuildId,
+) -> bool {
+    let everyone = serenity::PermissionOverwriteType::Role(guild_id.everyone_role());
+    permissions
+        .iter()
+        .any(|overwrite| overwrite.kind == everyone && overwrite.deny.view_channel())
+}
+
+// Whether a member has one of vc_mandatory_roles, which creating and joining private VCs need
+// Entries that aren't role IDs are skipped
+pub fn has_mandatory_role(roles: &[serenity::RoleId], mandatory_roles: &[String]) -> bool {
+    mandatory_roles
+        .iter()
+        .filter_map(|id| id.parse::<u64>().ok())
+        .any(|id| roles.iter().any(|role| role.get() == id))
+}
+
+// Let a member into a private VC, like an invite on /createvc would have
+// Anything else their overwrite already says is kept
+pub async fn grant_access(
+    api: &dyn DiscordApi,
+    guild_id: serenity::GuildId,
+    channel_id: serenity::ChannelId,
+    user_id: serenity::UserId,
+    audit_reason: &str,
+) -> Result<(), VoicersError> {
+    let channel = api
+        .guild_channels(guild_id)
+        .await?
+        .into_iter()
+        .find(|channel| channel.id == channel_id)
+        .ok_or_else(|| {
+            VoicersError::NotFound("That voice channel doesn't exist anymore".to_string())
+        })?;
+
+    let member = serenity::PermissionOverwriteType::Member(user_id);
+    let mut permissions = channel.permission_overwrites;
+    match permissions
+        .iter_mut()
+        .find(|overwrite| overwrite.kind == member)
+    {
+        Some(overwrite) => {
+            overwrite.allow |= serenity::Permissions::VIEW_CHANNEL;
+            overwrite.deny &= !serenity::Permissions::VIEW_CHANNEL;
+        }
+        None => permissions.push(serenity::PermissionOverwrite {
+            allow: serenity::Permissions::VIEW_CHANNEL,
+            deny: serenity::Permissions::empty(),
+            kind: member,
+        }),
+    }
+
+    api.edit_channel(
+        channel_id,
+        ChannelEdit {
+            permissions: Some(permissions),
+            audit_reason: audit_reason.to_string(),
+            ..ChannelEdit::default()
+        },
+    )
+    .await?;
+    info!(
+        guild_id = guild_id.get(),
+        vc_id = channel_id.get(),
+        user_id = user_id.get(),
+        "Let a member into a private voice channel"
+    );
+    Ok(())
+}
+
+// Tracked channels that have been empty for longer than the timeout
+pub async fn expired_channels(
+    pool: &SqlitePool,
+    now: i64,
+    timeout: i64,
+) -> Result<Vec<User>, VoicersError> {
+    let rows = storage::tracked_channels(pool).await?;
+    Ok(rows
+        .into_iter()
+        .filter(|row| row.user_count == 0 && (now - row.last_update) > timeout)
+        .collect())
+}
+
+// Delete an expired channel and stop tracking it
+// If Discord refuses the row stays, so the next pass tries again
+pub async fn reap_channel(
+    api: &dyn DiscordApi,
+    pool: &SqlitePool,
+    vc_id: i64,
+    guild_id: i64,
+) -> Result<bool, VoicersError> {
+    debug!(guild_id, vc_id, "Deleting voice channel");
+    match api
+        .delete_channel(
+            serenity::ChannelId::new(vc_id as u64),
+            "Cleaning up inactive voice channel",
+        )
+        .await
+    {
+        Ok(_) => {
+            storage::remove_channel(pool, vc_id).await?;
+            metrics::CHANNELS_DELETED
+                .with_label_values(&["expired"])
+                .inc();
+            info!(guild_id, vc_id, "Deleted voice channel");
+            Ok(true)
+        }
+        Err(why) => {
+            metrics::CHANNEL_DELETE_FAILURES.inc();
+            error!(
+                guild_id,
+                vc_id,
+                error = ?why,
+                "Failed to delete voice channel"
+            );
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{new_channel, test_pool, FakeGuild, PausedClock, BOT, GUILD};
+
+    async fn tracked_count(pool: &SqlitePool) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn vctype_parses_in_any_case() {
+        assert_eq!(VcType::parse("Private").unwrap(), VcType::Private);
+        assert_eq!(VcType::parse(" PUBLIC ").unwrap(), VcType::Public);
+        assert!(matches!(
+            VcType::parse("secret"),
+            Err(VoicersError::Validation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn create_vc_builds_a_private_channel() {
+        let api = FakeGuild::new();
+        let pool = test_pool(true).await;
+        let mut config = Config::default();
+        config.moderation.moderator_roles = vec!["77".to_string()];
+        let owner = api.add_member(10, "alice", &[]);
+        let friend = serenity::UserId::new(11);
+
+        let request = CreateRequest {
+            vc_type: VcType::Private,
+            name: None,
+            owner_name: "alice".to_string(),
+            invited_users: vec![friend],
+            invited_roles: Vec::new(),
+            settings: VoiceSettings::default(),
+        };
+        let channel_id = create_vc(
+            &api,
+            &pool,
+            &config,
+            &PausedClock::new(),
+            GUILD,
+            owner,
+            request,
+        )
+        .await
+        .unwrap();
+
+        let channel = api.state().channels[&channel_id].clone();
+        assert!(is_private(&channel, GUILD));
+        assert!(is_bot_created(&channel, None, GUILD, BOT));
+        let allowed = |kind| {
+            channel
+                .permission_overwrites
+                .iter()
+                .find(|overwrite| overwrite.kind == kind)
+                .map(|overwrite| overwrite.allow)
+        };
+        let view = serenity::Permissions::VIEW_CHANNEL;
+        assert_eq!(
+            allowed(serenity::PermissionOverwriteType::Member(owner)),
+            Some(view)
+        );
+        assert_eq!(
+            allowed(serenity::PermissionOverwriteType::Member(friend)),
+            Some(view)
+        );
+        assert_eq!(
+            allowed(serenity::PermissionOverwriteType::Role(
+                serenity::RoleId::new(77)
+            )),
+            Some(view | serenity::Permissions::MANAGE_CHANNELS)
+        );
+        assert_eq!(tracked_count(&pool).await, 1);
+    }
+
+    #[tokio::test]
+    async fn creates_and_tracks_channel() {
+        let api = FakeGuild::new();
+        let pool = test_pool(true).await;
+
+        let channel_id = create_tracked_channel(
+            &api,
+            &pool,
+            serenity::GuildId::new(1),
+            None,
+            new_channel(),
+            100,
+        )
+        .await
+        .unwrap();
+
+        assert!(api.has_channel(channel_id));
+        assert_eq!(tracked_count(&pool).await, 1);
+    }
+
+    #[tokio::test]
+    async fn discord_failure_writes_no_row() {
+        let api = FakeGuild::new();
+        api.state().fail_create = true;
+        let pool = test_pool(true).await;
+
+        let result = create_tracked_channel(
+            &api,
+            &pool,
+            serenity::GuildId::new(1),
+            None,
+            new_channel(),
+            100,
+        )
+        .await;
+
+        assert!(matches!(result, Err(VoicersError::Discord(_))));
+        assert_eq!(tracked_count(&pool).await, 0);
+    }
+
+    #[tokio::test]
+    async fn database_failure_deletes_the_channel_again() {
+        let api = FakeGuild::new();
+        // No schema, so the INSERT fails
+        let pool = test_pool(false).await;
+
+        let result = create_tracked_channel(
+            &api,
+            &pool,
+            serenity::GuildId::new(1),
+            None,
+            new_channel(),
+            100,
+        )
+        .await;
+
+        assert!(matches!(result, Err(VoicersError::Database(_))));
+        let state = api.state();
+        assert_eq!(state.deleted.len(), 1);
+        assert!(state.channels.is_empty());
+    }
+
+    const CATEGORY: serenity::ChannelId = serenity::ChannelId::new(7);
+
+    fn voice_channel(id: u64, bot_marker: bool) -> ChannelInfo {
+        let mut permission_overwrites = Vec::new();
+        if bot_marker {
+            // What /createvc sets up for a public VC
+            permission_overwrites.push(serenity::PermissionOverwrite {
+                allow: serenity::Permissions::VIEW_CHANNEL,
+                deny: serenity::Permissions::empty(),
+                kind: serenity::PermissionOverwriteType::Role(
+                    serenity::GuildId::new(1).everyone_role(),
+                ),
+            });
+            permission_overwrites.push(serenity::PermissionOverwrite {
+                allow: serenity::Permissions::MANAGE_CHANNELS | serenity::Permissions::VIEW_CHANNEL,
+                deny: serenity::Permissions::empty(),
+                kind: serenity::PermissionOverwriteType::Member(BOT),
+            });
+        }
+        ChannelInfo {
+            id: serenity::ChannelId::new(id),
+            name: format!("vc_{}", id),
+            kind: serenity::ChannelType::Voice,
+            parent_id: Some(CATEGORY),
+            permission_overwrites,
+            settings: VoiceSettings::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn adopts_untracked_bot_channels_only() {
+        let api = FakeGuild::new();
+        // 1 is already tracked, 2 is an orphan, 3 was made by a human
+        api.add_channel(voice_channel(1, true));
+        api.add_channel(voice_channel(2, true));
+        api.add_channel(voice_channel(3, false));
+        let pool = test_pool(true).await;
+        storage::insert_channel(&pool, 1, 1, None, 50)
+            .await
+            .unwrap();
+
+        let report = reconcile_orphans(
+            &api,
+            &pool,
+            serenity::GuildId::new(1),
+            &[CATEGORY],
+            BOT,
+            OrphanPolicy::Adopt,
+            100,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            report,
+            OrphanReport {
+                adopted: 1,
+                deleted: 0
+            }
+        );
+        let mut tracked = storage::tracked_channel_ids(&pool, 1).await.unwrap();
+        tracked.sort();
+        assert_eq!(tracked, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn deletes_orphans_when_configured() {
+        let api = FakeGuild::new();
+        api.add_channel(voice_channel(2, true));
+        let pool = test_pool(true).await;
+
+        let report = reconcile_orphans(
+            &api,
+            &pool,
+            serenity::GuildId::new(1),
+            &[CATEGORY],
+            BOT,
+            OrphanPolicy::Delete,
+            100,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            report,
+            OrphanReport {
+                adopted: 0,
+                deleted: 1
+            }
+        );
+        assert_eq!(api.state().deleted, vec![serenity::ChannelId::new(2)]);
+        assert_eq!(tracked_count(&pool).await, 0);
+    }
+
+    #[tokio::test]
+    async fn create_limits_cover_cooldown_user_and_guild() {
+        let pool = test_pool(true).await;
+        let guild = serenity::GuildId::new(1);
+        let alice = serenity::UserId::new(10);
+        let bob = serenity::UserId::new(11);
+        let voice = Voice {
+            max_channels_per_user: 1,
+            max_channels_per_guild: 2,
+            create_cooldown: 90,
+            ..Voice::default()
+        };
+        let check = |user, now| reserve_create_slot(&pool, &voice, guild, user, now);
+        let message = |result: Result<(), VoicersError>| match result {
+            Err(VoicersError::Validation(message)) => message,
+            other => panic!("expected a validation error, got {:?}", other),
+        };
+
+        check(alice, 0).await.unwrap();
+        storage::insert_channel(&pool, 1, 1, Some(10), 0)
+            .await
+            .unwrap();
+        storage::finish_create(&pool, 1, 10).await.unwrap();
+
+        assert!(message(check(alice, 30).await).contains("1 minute"));
+        assert!(message(check(alice, 90).await).contains("already have 1"));
+        check(bob, 90).await.unwrap();
+
+        storage::insert_channel(&pool, 2, 1, Some(11), 90)
+            .await
+            .unwrap();
+        storage::finish_create(&pool, 1, 11).await.unwrap();
+        assert!(message(check(serenity::UserId::new(12), 90).await).contains("server"));
+    }
+
+    #[tokio::test]
+    async fn concurrent_creates_get_one_slot() {
+        let api = FakeGuild::new();
+        let pool = test_pool(true).await;
+        let mut config = Config::default();
+        config.voice.max_channels_per_user = 1;
+        let owner = api.add_member(10, "alice", &[]);
+        let clock = PausedClock::new();
+        let create = || {
+            let request = CreateRequest {
+                vc_type: VcType::Public,
+                name: Some("race".to_string()),
+                owner_name: "alice".to_string(),
+                invited_users: Vec::new(),
+                invited_roles: Vec::new(),
+                settings: VoiceSettings::default(),
+            };
+            create_vc(&api, &pool, &config, &clock, GUILD, owner, request)
+        };
+
+        let (first, second) = tokio::join!(create(), create());
+
+        assert!(first.is_ok() != second.is_ok());
+        assert_eq!(api.state().channels.len(), 1);
+        assert_eq!(tracked_count(&pool).await, 1);
+    }
+
+    #[tokio::test]
+    async fn a_failed_create_hands_the_slot_back() {
+        let api = FakeGuild::new();
+        let pool = test_pool(true).await;
+        let mut config = Config::default();
+        config.voice.create_cooldown = 90;
+        let owner = api.add_member(10, "alice", &[]);
+        let request = || CreateRequest {
+            vc_type: VcType::Public,
+            name: Some("retry".to_string()),
+            owner_name: "alice".to_string(),
+            invited_users: Vec::new(),
+            invited_roles: Vec::new(),
+            settings: VoiceSettings::default(),
+        };
+        let clock = PausedClock::new();
+
+        api.state().fail_create = true;
+        assert!(
+            create_vc(&api, &pool, &config, &clock, GUILD, owner, request())
+                .await
+                .is_err()
+        );
+
+        // No cooldown for a channel that never showed up
+        api.state().fail_create = false;
+        create_vc(&api, &pool, &config, &clock, GUILD, owner, request())
+            .await
+            .unwrap();
+        assert!(
+            create_vc(&api, &pool, &config, &clock, GUILD, owner, request())
+                .await
+                .is_err()
+        );
+        assert_eq!(tracked_count(&pool).await, 1);
+    }
+
+    fn category(id: serenity::ChannelId) -> ChannelInfo {
+        ChannelInfo {
+            id,
+            name: format!("category_{}", id),
+            kind: serenity::ChannelType::Category,
+            parent_id: None,
+            permission_overwrites: Vec::new(),
+            settings: VoiceSettings::default(),
+        }
+    }
+
+    fn configured(categories: &[serenity::ChannelId], auto_create: bool) -> CategorySettings {
+        CategorySettings {
+            categories: categories.to_vec(),
+            auto_create,
+            moderator_roles: vec![serenity::RoleId::new(5)],
+        }
+    }
+
+    #[tokio::test]
+    async fn overflows_into_the_next_category_and_refuses_when_all_are_full() {
+        let api = FakeGuild::new();
+        let pool = test_pool(true).await;
+        let second = serenity::ChannelId::new(8);
+        let settings = configured(&[CATEGORY, second], false);
+        let guild = serenity::GuildId::new(1);
+        api.add_channel(category(CATEGORY));
+        api.add_channel(category(second));
+        for id in 0..CATEGORY_CHANNEL_LIMIT as u64 {
+            api.add_channel(voice_channel(100 + id, false));
+        }
+
+        assert_eq!(
+            choose_category(&api, &pool, guild, &settings)
+                .await
+                .unwrap(),
+            Some(second)
+        );
+        assert_eq!(
+            choose_category(&api, &pool, guild, &configured(&[], false))
+                .await
+                .unwrap(),
+            None
+        );
+
+        for id in 0..CATEGORY_CHANNEL_LIMIT as u64 {
+            api.add_channel(ChannelInfo {
+                parent_id: Some(second),
+                ..voice_channel(200 + id, false)
+            });
+        }
+        assert!(matches!(
+            choose_category(&api, &pool, guild, &settings).await,
+            Err(VoicersError::Validation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_category_falls_back_to_top_level_or_an_auto_created_one() {
+        let api = FakeGuild::new();
+        let pool = test_pool(true).await;
+        let guild = serenity::GuildId::new(1);
+        // CATEGORY was never created in this guild, like a deleted one
+
+        assert_eq!(
+            choose_category(&api, &pool, guild, &configured(&[CATEGORY], false))
+                .await
+                .unwrap(),
+            None
+        );
+
+        let settings = configured(&[CATEGORY], true);
+        let created = choose_category(&api, &pool, guild, &settings)
+            .await
+            .unwrap()
+            .unwrap();
+        let channel = api.state().channels[&created].clone();
+        assert_eq!(channel.kind, serenity::ChannelType::Category);
+        assert_eq!(channel.name, AUTO_CATEGORY_NAME);
+        assert!(channel.permission_overwrites.iter().any(|overwrite| {
+            overwrite.kind == serenity::PermissionOverwriteType::Role(serenity::RoleId::new(5))
+        }));
+
+        // Remembered, so the next VC goes in the same one
+        assert_eq!(
+            choose_category(&api, &pool, guild, &settings)
+                .await
+                .unwrap(),
+            Some(created)
+        );
+        assert_eq!(api.state().channels.len(), 1);
+
+        // And made again if someone deletes it
+        api.delete_channel(created, "test").await.unwrap();
+        let recreated = choose_category(&api, &pool, guild, &settings)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(recreated, created);
+        assert_eq!(
+            storage::guild_category(&pool, 1).await.unwrap(),
+            Some(recreated.get() as i64)
+        );
+    }
+
+    #[tokio::test]
+    async fn granting_access_lets_a_knocker_into_a_private_vc() {
+        let api = FakeGuild::new();
+        let guild = serenity::GuildId::new(1);
+        let knocker = serenity::UserId::new(11);
+        let mut channel = voice_channel(100, false);
+        channel
+            .permission_overwrites
+            .push(serenity::PermissionOverwrite {
+                allow: serenity::Permissions::empty(),
+                deny: serenity::Permissions::VIEW_CHANNEL,
+                kind: serenity::PermissionOverwriteType::Role(guild.everyone_role()),
+            });
+        // Kept out on purpose earlier, approving still has to win
+        channel
+            .permission_overwrites
+            .push(serenity::PermissionOverwrite {
+                allow: serenity::Permissions::empty(),
+                deny: serenity::Permissions::VIEW_CHANNEL | serenity::Permissions::SPEAK,
+                kind: serenity::PermissionOverwriteType::Member(knocker),
+            });
+        assert!(is_private(&channel, guild));
+        assert!(!is_private(&voice_channel(101, true), guild));
+        api.add_channel(channel.clone());
+
+        grant_access(&api, guild, channel.id, knocker, "test")
+            .await
+            .unwrap();
+
+        let overwrites = api.state().channels[&channel.id]
+            .permission_overwrites
+            .clone();
+        assert_eq!(overwrites.len(), 2);
+        let overwrite = overwrites
+            .iter()
+            .find(|overwrite| overwrite.kind == serenity::PermissionOverwriteType::Member(knocker))
+            .unwrap();
+        assert!(overwrite.allow.view_channel());
+        assert_eq!(overwrite.deny, serenity::Permissions::SPEAK);
+
+        assert!(matches!(
+            grant_access(&api, guild, serenity::ChannelId::new(999), knocker, "test").await,
+            Err(VoicersError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn mandatory_roles_skip_entries_that_arent_ids() {
+        let roles = [serenity::RoleId::new(7)];
+        let mandatory = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        assert!(has_mandatory_role(&roles, &mandatory(&["", "0", "7"])));
+        assert!(!has_mandatory_role(
+            &roles,
+            &mandatory(&["8", "not a role"])
+        ));
+        assert!(!has_mandatory_role(&roles, &[]));
+    }
+
+    #[tokio::test]
+    async fn hand_made_channels_in_bot_managed_categories_survive_reconcile() {
+        let api = FakeGuild::new();
+        let pool = test_pool(true).await;
+        let guild = serenity::GuildId::new(1);
+        let auto = choose_category(&api, &pool, guild, &configured(&[], true))
+            .await
+            .unwrap()
+            .unwrap();
+        // A private category an admin let the bot manage, its overwrites look a lot like a temp VC's
+        let private = category(CATEGORY);
+        let private = ChannelInfo {
+            permission_overwrites: voice_channel(1, true)
+                .permission_overwrites
+                .into_iter()
+                .map(|overwrite| match overwrite.kind {
+                    serenity::PermissionOverwriteType::Role(_) => serenity::PermissionOverwrite {
+                        allow: serenity::Permissions::empty(),
+                        deny: serenity::Permissions::VIEW_CHANNEL,
+                        ..overwrite
+                    },
+                    _ => overwrite,
+                })
+                .collect(),
+            ..private
+        };
+        api.add_channel(private.clone());
+
+        // Channels made by hand in either category, synced to it
+        let mut hand_made = Vec::new();
+        for (id, parent) in [(2, auto), (3, CATEGORY)] {
+            let channel = ChannelInfo {
+                parent_id: Some(parent),
+                permission_overwrites: api.state().channels[&parent].permission_overwrites.clone(),
+                ..voice_channel(id, false)
+            };
+            api.add_channel(channel.clone());
+            hand_made.push(channel.id);
+        }
+        let orphan = ChannelInfo {
+            parent_id: Some(auto),
+            ..voice_channel(4, true)
+        };
+        api.add_channel(orphan.clone());
+
+        let report = reconcile_orphans(
+            &api,
+            &pool,
+            guild,
+            &[auto, CATEGORY],
+            BOT,
+            OrphanPolicy::Delete,
+            100,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            report,
+            OrphanReport {
+                adopted: 0,
+                deleted: 1
+            }
+        );
+        for channel_id in hand_made {
+            assert!(api.has_channel(channel_id));
+        }
+        assert_eq!(api.state().deleted, vec![orphan.id]);
+    }
+}
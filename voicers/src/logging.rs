@@ -0,0 +1,287 @@
+use crate::config::{LogFormat, LogRotation, Logging};
+use colored::Colorize;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::io::{self, Write};
+use tracing::level_filters::LevelFilter;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{InitError, RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+// Set up the global subscriber from the [logging] section
+// The returned guard flushes the file sink when dropped, so main has to hold on to it
+pub fn init(level: LevelFilter, config: &Logging) -> Option<WorkerGuard> {
+    let mut layers: Vec<BoxedLayer> = vec![fmt_layer(
+        config.format,
+        RedactingMakeWriter::new(io::stdout),
+    )];
+
+    // A directory we can't write to only costs the file log, the bot still runs
+    let guard = match config
+        .file_directory
+        .as_ref()
+        .map(|directory| file_appender(config, directory))
+    {
+        Some(Ok(appender)) => {
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            layers.push(fmt_layer(config.format, RedactingMakeWriter::new(writer)));
+            Some(guard)
+        }
+        Some(Err(e)) => {
+            println!(
+                "{}Unable to log to file_directory, only logging to stdout: {}",
+                "Warn:".yellow().bold(),
+                e
+            );
+            None
+        }
+        None => None,
+    };
+
+    let subscriber = tracing_subscriber::registry()
+        .with(layers)
+        .with(filter(level, config));
+    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+
+    guard
+}
+
+// The level is the default, the filter directives narrow it down per target
+// with_default_directive would drop the level as soon as there are any directives, so it goes in the string
+fn filter(level: LevelFilter, config: &Logging) -> EnvFilter {
+    let directives = if config.filter.is_empty() {
+        level.to_string()
+    } else {
+        format!("{},{}", level, config.filter)
+    };
+    EnvFilter::builder().parse_lossy(directives)
+}
+
+// The directory gets created if it's missing
+fn file_appender(config: &Logging, directory: &str) -> Result<RollingFileAppender, InitError> {
+    RollingFileAppender::builder()
+        .rotation(rotation(config.file_rotation))
+        .filename_prefix(&config.file_prefix)
+        .build(directory)
+}
+
+fn fmt_layer<W>(format: LogFormat, writer: W) -> BoxedLayer
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().with_writer(writer).boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_writer(writer)
+            .boxed(),
+    }
+}
+
+fn rotation(rotation: LogRotation) -> Rotation {
+    match rotation {
+        LogRotation::Minutely => Rotation::MINUTELY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    }
+}
+
+// Anything shaped like a Discord bot token: base64 user ID, timestamp and HMAC separated by dots
+// Deliberately loose, a false positive in a log line costs nothing
+static TOKEN_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"[A-Za-z0-9_-]{23,28}\.[A-Za-z0-9_-]{6,7}\.[A-Za-z0-9_-]{27,}|mfa\.[A-Za-z0-9_-]{20,}",
+    )
+    .expect("Token pattern is a valid regex")
+});
+
+const REDACTED: &str = "[REDACTED]";
+
+pub fn redact(text: &str) -> std::borrow::Cow<'_, str> {
+    TOKEN_PATTERN.replace_all(text, REDACTED)
+}
+
+// Wraps a MakeWriter so every log line goes through redact() before it hits the output
+// tracing-subscriber formats a whole event and writes it in one go, so tokens never get split
+pub struct RedactingMakeWriter<M> {
+    inner: M,
+}
+
+impl<M> RedactingMakeWriter<M> {
+    pub fn new(inner: M) -> Self {
+        RedactingMakeWriter { inner }
+    }
+}
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for RedactingMakeWriter<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter {
+            inner: self.inner.make_writer(),
+        }
+    }
+}
+
+pub struct RedactingWriter<W> {
+    inner: W,
+}
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        self.inner.write_all(redact(&text).as_bytes())?;
+        // Report the original length, the caller doesn't care that we wrote something shorter
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // Collects everything written to it, for looking at what a subscriber printed
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    // A fresh directory under the system temp dir, unique per test
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("voicers-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn filter_directives_override_the_level_per_target() {
+        let config = Logging {
+            filter: "serenity=warn,voicers::reaper=debug".to_string(),
+            ..Logging::default()
+        };
+        let output = Captured::default();
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(fmt_layer(LogFormat::Pretty, move || writer.clone()))
+            .with(filter(LevelFilter::INFO, &config));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "serenity::gateway", "gateway chatter");
+            tracing::warn!(target: "serenity::gateway", "gateway trouble");
+            tracing::debug!(target: "voicers::reaper", "reaper details");
+            tracing::debug!(target: "voicers::storage", "storage details");
+            tracing::info!(target: "voicers::storage", "storage news");
+        });
+
+        let text = output.text();
+        assert!(!text.contains("gateway chatter"));
+        assert!(text.contains("gateway trouble"));
+        assert!(text.contains("reaper details"));
+        assert!(!text.contains("storage details"));
+        assert!(text.contains("storage news"));
+    }
+
+    #[test]
+    fn json_lines_land_in_the_log_directory() {
+        let directory = temp_dir("json-log");
+        let config = Logging {
+            format: LogFormat::Json,
+            file_rotation: LogRotation::Never,
+            ..Logging::default()
+        };
+        let appender = file_appender(&config, directory.to_str().unwrap()).unwrap();
+        let subscriber = tracing_subscriber::registry()
+            .with(fmt_layer(config.format, RedactingMakeWriter::new(appender)));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(vc_id = 5, "Deleted voice channel");
+        });
+
+        let file = std::fs::read_dir(&directory)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert!(file
+            .file_name()
+            .to_string_lossy()
+            .starts_with(&config.file_prefix));
+        let text = std::fs::read_to_string(file.path()).unwrap();
+        let line = text.lines().next().unwrap();
+        assert!(line.starts_with('{') && line.ends_with('}'));
+        assert!(line.contains(r#""message":"Deleted voice channel""#));
+        assert!(line.contains(r#""vc_id":5"#));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn an_unusable_log_directory_is_an_error_not_a_panic() {
+        // A file where the directory should be
+        let directory = temp_dir("not-a-dir");
+        std::fs::write(&directory, "").unwrap();
+        let directory = directory.join("logs");
+
+        assert!(file_appender(&Logging::default(), directory.to_str().unwrap()).is_err());
+        std::fs::remove_file(directory.parent().unwrap()).unwrap();
+    }
+
+    // Made up, but shaped like the real thing
+    const TOKEN: &str = "MTIzNDU2Nzg5MDEyMzQ1Njc4.GaBcDe.abcdefghijklmnopqrstuvwxyz0123";
+
+    #[test]
+    fn tokens_in_a_line_are_masked() {
+        let line = format!("Connecting with token {} to the gateway", TOKEN);
+        assert_eq!(
+            redact(&line),
+            "Connecting with token [REDACTED] to the gateway"
+        );
+        assert_eq!(redact("mfa.abcdefghijklmnopqrstuvwxyz"), "[REDACTED]");
+        // Ordinary lines come back untouched
+        assert_eq!(
+            redact("Deleted voice channel 1234"),
+            "Deleted voice channel 1234"
+        );
+    }
+
+    #[test]
+    fn the_writer_masks_before_writing() {
+        let mut output = Vec::new();
+        let line = format!("bot_token = \"{}\"\n", TOKEN);
+        let written = RedactingWriter { inner: &mut output }
+            .write(line.as_bytes())
+            .unwrap();
+
+        assert_eq!(written, line.len());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "bot_token = \"[REDACTED]\"\n"
+        );
+    }
+}
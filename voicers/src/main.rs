@@ -1,78 +1,143 @@
-use sqlx::sqlite::SqlitePool;
-use std::fs;
-use std::path::Path;
+use std::process::ExitCode;
 use std::sync::Arc;
-use tracing::{debug, info, warn};
-use tracing_subscriber::FmtSubscriber;
-
-// MODULES BABBBBBYYYYYY
-mod commands;
-mod config;
-mod discord;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info};
+use voicers::{config, discord, health, http, logging, metrics, shutdown, storage};
 
+// Returns instead of calling process::exit so the log guard gets dropped and flushes the file logs
 #[tokio::main]
-async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+async fn main() -> ExitCode {
+    // Subcommands have to run before anything touches the config, since loading it creates the file
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(subcommand) = args.first() {
+        match subcommand.as_str() {
+            "init-config" => return init_config(&args[1..]),
+            _ => {
+                eprintln!("Unknown subcommand: {}", subcommand);
+                eprintln!("Usage: voicers [init-config [path] [--force]]");
+                return ExitCode::from(2);
+            }
+        }
+    }
+
     // Initialize the logging
     let logging_config = config::get_logging_config();
 
     // Set up the tracing subscriber here
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(logging_config)
-        .finish();
-
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    // Keep the guard alive for the whole run or buffered file logs get lost
+    let _log_guard = logging::init(logging_config, &config::get_config().logging);
 
     // Initialize database
-    let db_path = "VCs.db";
-    initialize_database(db_path).expect("Failed to initialize database");
-
     info!("Database initializing...");
-    // Connect to the database
-    let database_url = format!("sqlite:{}", db_path);
-    let pool = SqlitePool::connect(&database_url).await?;
+    let pool = match storage::open("VCs.db").await {
+        Ok(pool) => pool,
+        Err(e) => {
+            error!("Couldn't open the database: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
     let shared_pool = Arc::new(pool);
 
-    let create_table_query = r#"
-    CREATE TABLE IF NOT EXISTS users (
-        vc_id INTEGER PRIMARY KEY,
-        guild_id INTEGER NOT NULL,
-        last_update INTEGER NOT NULL,
-        user_count INTEGER NOT NULL 
-        );
-    "#;
-    sqlx::query(create_table_query)
-        .execute(&*shared_pool)
-        .await
-        .expect("Failed to create VC table");
-
     info!("Starting voiceRS...");
 
     // initialize the features config
     let features_config = config::get_features_config();
     debug!("Disabled features: {:?}", features_config.disabled_features);
 
+    // Cancelled on SIGINT/SIGTERM, or when the bot dies so the HTTP server goes down with it
+    let shutdown = CancellationToken::new();
+    tokio::spawn(shutdown::listen_for_signals(shutdown.clone()));
+
     // Tasks Vector for Tokio tasks
+    // Each task reports whether it stopped cleanly
     let mut tasks = Vec::new();
 
+    // Shared health state, written by the bot and read by the HTTP server
+    let health = Arc::new(health::Health::new());
+
+    // The HTTP server is optional and exposes metrics and health checks
+    let config = config::get_config();
+    if config.http.enabled {
+        metrics::init();
+        let pool = shared_pool.clone();
+        let health = health.clone();
+        let shutdown = shutdown.clone();
+        tasks.push(tokio::spawn(async move {
+            match http::serve(config, pool, health, shutdown).await {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("HTTP server stopped: {}", e);
+                    false
+                }
+            }
+        }));
+    }
+
     // Finally begin working on Discord bot
     // immediately async the bot onto it's own thread
-    let _bot_handle = tasks.push(tokio::spawn(async {
-        discord::start_discord_bot(shared_pool).await;
+    let bot_pool = shared_pool.clone();
+    let bot_shutdown = shutdown.clone();
+    tasks.push(tokio::spawn(async move {
+        // Whatever happened, a panic included, nothing else should keep running without the bot
+        let _stop_the_rest = bot_shutdown.clone().drop_guard();
+        let result = discord::start_discord_bot(bot_pool, health, bot_shutdown).await;
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Discord bot stopped: {}", e);
+                false
+            }
+        }
     }));
 
     // Wait for all the spawned tasks to complete
+    let mut clean_exit = true;
     for task in tasks {
-        let _ = task.await?;
+        match task.await {
+            Ok(clean) => clean_exit &= clean,
+            Err(e) => {
+                // A panicked task still has to take the rest down and let the pool close
+                error!("Task died: {}", e);
+                shutdown.cancel();
+                clean_exit = false;
+            }
+        }
     }
 
-    Ok(())
+    // Only close the pool once nothing is left that could still write to it
+    info!("Closing the database...");
+    shared_pool.close().await;
+
+    if !clean_exit {
+        return ExitCode::FAILURE;
+    }
+
+    info!("Shut down cleanly");
+    ExitCode::SUCCESS
 }
 
-fn initialize_database(db_path: &str) -> std::io::Result<()> {
-    if !Path::new(db_path).exists() {
-        warn!("Database does not exist, creating it...");
-        // Create an empty file to initialize the database
-        fs::File::create(db_path)?;
+// voicers init-config [path] [--force]
+// Writes the generated default config so people don't have to start the bot to get one
+fn init_config(args: &[String]) -> ExitCode {
+    let force = args.iter().any(|arg| arg == "--force");
+    let path = args
+        .iter()
+        .find(|arg| !arg.starts_with("--"))
+        .map(String::as_str)
+        .unwrap_or("config.toml");
+
+    match config::write_default_config(path, force) {
+        Ok(()) => {
+            println!("Wrote default config to {}", path);
+            ExitCode::SUCCESS
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            eprintln!("{} already exists, pass --force to overwrite it", path);
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("Couldn't write {}: {}", path, e);
+            ExitCode::FAILURE
+        }
     }
-    Ok(())
 }
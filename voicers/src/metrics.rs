@@ -0,0 +1,190 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+// Our own registry instead of the global default so only VoiceRS metrics end up on /metrics
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(metric: T) -> T {
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Metric names are unique");
+    metric
+}
+
+pub static CHANNELS_CREATED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "voicers_channels_created_total",
+                "Temporary voice channels created, by type",
+            ),
+            &["type"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static CHANNELS_DELETED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "voicers_channels_deleted_total",
+                "Temporary voice channels deleted, by reason (expired or orphaned)",
+            ),
+            &["reason"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static CHANNEL_DELETE_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    register(
+        IntCounter::new(
+            "voicers_channel_delete_failures_total",
+            "Failed attempts to delete a temporary voice channel",
+        )
+        .unwrap(),
+    )
+});
+
+pub static ACTIVE_CHANNELS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new(
+                "voicers_active_channels",
+                "Temporary voice channels currently tracked in the database, by guild",
+            ),
+            &["guild_id"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static VOICE_STATE_EVENTS: Lazy<IntCounter> = Lazy::new(|| {
+    register(
+        IntCounter::new(
+            "voicers_voice_state_events_total",
+            "Voice state updates processed",
+        )
+        .unwrap(),
+    )
+});
+
+pub static REAPER_LOOP_DURATION: Lazy<Histogram> = Lazy::new(|| {
+    register(
+        Histogram::with_opts(HistogramOpts::new(
+            "voicers_reaper_loop_duration_seconds",
+            "Time spent in one pass of the reaper loop, not counting the sleep",
+        ))
+        .unwrap(),
+    )
+});
+
+pub static COMMAND_INVOCATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "voicers_command_invocations_total",
+                "Command invocations, by command name",
+            ),
+            &["command"],
+        )
+        .unwrap(),
+    )
+});
+
+// Touch every metric so they all show up on /metrics before anything happens
+pub fn init() {
+    Lazy::force(&CHANNELS_CREATED);
+    Lazy::force(&CHANNELS_DELETED);
+    Lazy::force(&CHANNEL_DELETE_FAILURES);
+    Lazy::force(&ACTIVE_CHANNELS);
+    Lazy::force(&VOICE_STATE_EVENTS);
+    Lazy::force(&REAPER_LOOP_DURATION);
+    Lazy::force(&COMMAND_INVOCATIONS);
+}
+
+// Render everything in the Prometheus text format
+pub fn gather() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("Encoding metrics into a Vec can't fail");
+    String::from_utf8(buffer).expect("Prometheus text format is UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OrphanPolicy;
+    use crate::discord_api::{ChannelInfo, VoiceSettings};
+    use crate::lifecycle;
+    use crate::testing::{new_channel, test_pool, FakeGuild, BOT, GUILD};
+    use poise::serenity_prelude as serenity;
+
+    // The counters are shared with every other test, so only look at how much they went up
+    #[tokio::test]
+    async fn creates_and_deletes_show_up_on_metrics() {
+        let api = FakeGuild::new();
+        let pool = test_pool(true).await;
+        let created = CHANNELS_CREATED.with_label_values(&["public"]).get();
+        let expired = CHANNELS_DELETED.with_label_values(&["expired"]).get();
+        let orphaned = CHANNELS_DELETED.with_label_values(&["orphaned"]).get();
+
+        let channel_id =
+            lifecycle::create_tracked_channel(&api, &pool, GUILD, None, new_channel(), 100)
+                .await
+                .unwrap();
+        assert!(
+            lifecycle::reap_channel(&api, &pool, channel_id.get() as i64, GUILD.get() as i64)
+                .await
+                .unwrap()
+        );
+
+        // A bot made channel in our category the database doesn't know about
+        let category = serenity::ChannelId::new(500);
+        api.add_channel(ChannelInfo {
+            id: serenity::ChannelId::new(501),
+            name: "left behind".to_string(),
+            kind: serenity::ChannelType::Voice,
+            parent_id: Some(category),
+            permission_overwrites: vec![
+                serenity::PermissionOverwrite {
+                    allow: serenity::Permissions::MANAGE_CHANNELS
+                        | serenity::Permissions::VIEW_CHANNEL,
+                    deny: serenity::Permissions::empty(),
+                    kind: serenity::PermissionOverwriteType::Member(BOT),
+                },
+                serenity::PermissionOverwrite {
+                    allow: serenity::Permissions::VIEW_CHANNEL,
+                    deny: serenity::Permissions::empty(),
+                    kind: serenity::PermissionOverwriteType::Role(GUILD.everyone_role()),
+                },
+            ],
+            settings: VoiceSettings::default(),
+        });
+        let report = lifecycle::reconcile_orphans(
+            &api,
+            &pool,
+            GUILD,
+            &[category],
+            BOT,
+            OrphanPolicy::Delete,
+            100,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.deleted, 1);
+
+        assert!(CHANNELS_CREATED.with_label_values(&["public"]).get() > created);
+        assert!(CHANNELS_DELETED.with_label_values(&["expired"]).get() > expired);
+        assert!(CHANNELS_DELETED.with_label_values(&["orphaned"]).get() > orphaned);
+        let text = gather();
+        assert!(text.contains(r#"voicers_channels_created_total{type="public"}"#));
+        assert!(text.contains(r#"voicers_channels_deleted_total{reason="expired"}"#));
+        assert!(text.contains(r#"voicers_channels_deleted_total{reason="orphaned"}"#));
+    }
+}
@@ -0,0 +1,331 @@
+use crate::config::Voice;
+use crate::discord_api::DiscordApi;
+use crate::error::VoicersError;
+use crate::storage;
+use poise::serenity_prelude as serenity;
+use sqlx::SqlitePool;
+use tracing::warn;
+
+// Turning a name template into an actual channel name
+// Placeholders: {owner} {type} {counter} {date} {activity}, anything else is left as written
+
+// Discord won't take a channel name longer than this
+pub const MAX_NAME_LENGTH: usize = 100;
+
+// Everything a template can refer to
+pub struct NameParts<'a> {
+    pub owner: &'a str,
+    pub vc_type: &'a str,
+    pub counter: i64,
+    pub now: i64,
+    // Only there when presences are cached, which needs the presence intent
+    pub activity: Option<&'a str>,
+}
+
+// The bot only asks Discord for presences (a privileged intent) when the config's template
+// uses {activity}, so that's also the only time {activity} can be anything but empty
+pub fn wants_presences(voice: &Voice) -> bool {
+    voice.name_template.contains("{activity}")
+}
+
+// Fill in the placeholders in one pass so a nickname with {counter} in it stays literal
+pub fn render(template: &str, parts: &NameParts) -> String {
+    let mut name = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        name.push_str(&rest[..start]);
+        let after = &rest[start..];
+        let Some(end) = after.find('}') else {
+            break;
+        };
+        match placeholder(&after[1..end], parts) {
+            Some(value) => name.push_str(&value),
+            None => name.push_str(&after[..=end]),
+        }
+        rest = &after[end + 1..];
+    }
+    // Whatever is left has no placeholders in it, including an unclosed {
+    name.push_str(rest);
+
+    let name = sanitize(&name);
+    if name.is_empty() {
+        // A template that renders to nothing (just {activity} while idle) still needs a name
+        sanitize(&format!("{}'s VC", parts.owner))
+    } else {
+        name
+    }
+}
+
+fn placeholder(key: &str, parts: &NameParts) -> Option<String> {
+    match key {
+        "owner" => Some(parts.owner.to_string()),
+        "type" => Some(parts.vc_type.to_string()),
+        "counter" => Some(parts.counter.to_string()),
+        "date" => Some(
+            chrono::DateTime::from_timestamp(parts.now, 0)
+                .map(|date| date.format("%Y-%m-%d").to_string())
+                .unwrap_or_default(),
+        ),
+        "activity" => Some(parts.activity.unwrap_or_default().to_string()),
+        _ => None,
+    }
+}
+
+// No control characters or newlines, single spaces, and short enough for Discord
+pub fn sanitize(name: &str) -> String {
+    truncate(&clean(name), MAX_NAME_LENGTH)
+}
+
+fn clean(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    cleaned.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn truncate(name: &str, max_length: usize) -> String {
+    name.chars()
+        .take(max_length)
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+// Check a name against the config's filter, handing back the cleaned up version
+// Members get told why, moderators get a warning in the logs with who tried it
+pub fn check_name(
+    name: &str,
+    voice: &Voice,
+    guild_id: serenity::GuildId,
+    user_id: serenity::UserId,
+) -> Result<String, VoicersError> {
+    let name = clean(name);
+    let max_length = voice.name_max_length.clamp(1, MAX_NAME_LENGTH);
+
+    let rejection = if name.is_empty() {
+        Some("Voice channel names can't be empty".to_string())
+    } else if name.chars().count() > max_length {
+        Some(format!(
+            "Voice channel names can be at most {} characters long",
+            max_length
+        ))
+    } else if let Some(reason) = blocked(&name, voice) {
+        warn!(
+            guild_id = guild_id.get(),
+            user_id = user_id.get(),
+            name = %name,
+            reason = %reason,
+            "Blocked a voice channel name"
+        );
+        Some("That name isn't allowed here, please pick another one".to_string())
+    } else {
+        None
+    };
+
+    match rejection {
+        Some(message) => Err(VoicersError::Validation(message)),
+        None => Ok(name),
+    }
+}
+
+// Which blocklist entry or pattern the name ran into, if any
+fn blocked(name: &str, voice: &Voice) -> Option<String> {
+    let lowercase = name.to_lowercase();
+    if let Some(word) = voice
+        .name_blocklist
+        .iter()
+        .filter(|word| !word.is_empty())
+        .find(|word| lowercase.contains(&word.to_lowercase()))
+    {
+        return Some(format!("blocklist entry {:?}", word));
+    }
+
+    // Broken patterns were already reported when the config was loaded
+    voice
+        .name_patterns
+        .iter()
+        .find(|pattern| {
+            regex::RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .is_ok_and(|regex| regex.is_match(name))
+        })
+        .map(|pattern| format!("pattern {:?}", pattern))
+}
+
+// The name for a new VC when the owner didn't pick one
+// Uses the guild's template if a moderator set one, otherwise the one from the config
+#[allow(clippy::too_many_arguments)]
+pub async fn channel_name(
+    api: &dyn DiscordApi,
+    pool: &SqlitePool,
+    voice: &Voice,
+    guild_id: serenity::GuildId,
+    owner_id: serenity::UserId,
+    owner: &str,
+    vc_type: &str,
+    now: i64,
+) -> Result<String, VoicersError> {
+    let guild = guild_id.get() as i64;
+    let template = storage::name_template(pool, guild)
+        .await?
+        .unwrap_or_else(|| voice.name_template.clone());
+
+    // Only templates that show the counter move it forward
+    let counter = if template.contains("{counter}") {
+        storage::next_name_counter(pool, guild).await?
+    } else {
+        0
+    };
+    let activity = api.member_activity(guild_id, owner_id);
+
+    let name = render(
+        &template,
+        &NameParts {
+            owner,
+            vc_type,
+            counter,
+            now,
+            activity: activity.as_deref(),
+        },
+    );
+    // A long nickname isn't the owner's fault, so this gets cut instead of rejected
+    Ok(truncate(
+        &name,
+        voice.name_max_length.clamp(1, MAX_NAME_LENGTH),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{test_pool, FakeGuild, GUILD};
+
+    fn parts() -> NameParts<'static> {
+        NameParts {
+            owner: "alice",
+            vc_type: "private",
+            counter: 7,
+            // 2024-01-02 03:04:05 UTC
+            now: 1704164645,
+            activity: Some("Minecraft"),
+        }
+    }
+
+    #[test]
+    fn fills_in_every_placeholder() {
+        assert_eq!(
+            render("{owner} {type} #{counter} {date} {activity}", &parts()),
+            "alice private #7 2024-01-02 Minecraft"
+        );
+    }
+
+    #[test]
+    fn unknown_and_unclosed_placeholders_stay_as_written() {
+        assert_eq!(
+            render("{owner} {nope} {owner", &parts()),
+            "alice {nope} {owner"
+        );
+    }
+
+    #[test]
+    fn values_are_not_expanded_again() {
+        let parts = NameParts {
+            owner: "{counter}",
+            ..parts()
+        };
+        assert_eq!(render("{owner}", &parts), "{counter}");
+    }
+
+    #[test]
+    fn only_activity_templates_want_presences() {
+        let mut voice = Voice {
+            name_template: "{owner}'s VC".to_string(),
+            ..Voice::default()
+        };
+        assert!(!wants_presences(&voice));
+        voice.name_template = "{owner} playing {activity}".to_string();
+        assert!(wants_presences(&voice));
+    }
+
+    #[test]
+    fn missing_activity_collapses_and_empty_names_fall_back() {
+        let parts = NameParts {
+            activity: None,
+            ..parts()
+        };
+        assert_eq!(
+            render("{owner}  playing {activity}", &parts),
+            "alice playing"
+        );
+        assert_eq!(render("{activity}", &parts), "alice's VC");
+    }
+
+    #[test]
+    fn names_are_sanitized_and_cut_to_discords_limit() {
+        assert_eq!(sanitize(" a\nb\t\u{7}c  "), "a b c");
+        let long = "x".repeat(MAX_NAME_LENGTH + 20);
+        assert_eq!(sanitize(&long).chars().count(), MAX_NAME_LENGTH);
+    }
+
+    fn filtered() -> Voice {
+        Voice {
+            name_max_length: 10,
+            name_blocklist: vec!["badword".to_string()],
+            name_patterns: vec![r"discord\.gg/".to_string(), "(broken".to_string()],
+            ..Voice::default()
+        }
+    }
+
+    fn check(name: &str) -> Option<String> {
+        check_name(name, &filtered(), GUILD, serenity::UserId::new(10)).ok()
+    }
+
+    fn rejected(name: &str) -> bool {
+        matches!(
+            check_name(name, &filtered(), GUILD, serenity::UserId::new(10)),
+            Err(VoicersError::Validation(_))
+        )
+    }
+
+    #[test]
+    fn filter_accepts_clean_names_and_tidies_them() {
+        assert_eq!(check("  chill\n vc ").as_deref(), Some("chill vc"));
+    }
+
+    #[test]
+    fn filter_rejects_blocked_long_and_empty_names() {
+        assert!(rejected("my BadWord"));
+        assert!(rejected("DISCORD.GG/x"));
+        assert!(rejected("eleven char"));
+        assert!(rejected(" \t "));
+    }
+
+    #[tokio::test]
+    async fn guild_template_overrides_the_config_and_counts_up() {
+        let guild = FakeGuild::new();
+        let pool = test_pool(true).await;
+        let owner = guild.add_member(10, "alice", &[]);
+        let voice = Voice {
+            name_template: "{owner}'s VC".to_string(),
+            ..Voice::default()
+        };
+        let name = |pool| channel_name(&guild, pool, &voice, GUILD, owner, "alice", "public", 0);
+
+        assert_eq!(name(&pool).await.unwrap(), "alice's VC");
+
+        storage::set_name_template(&pool, GUILD.get() as i64, Some("Room {counter}"))
+            .await
+            .unwrap();
+        assert_eq!(name(&pool).await.unwrap(), "Room 1");
+        assert_eq!(name(&pool).await.unwrap(), "Room 2");
+
+        // Resetting goes back to the config's template
+        storage::set_name_template(&pool, GUILD.get() as i64, None)
+            .await
+            .unwrap();
+        assert_eq!(name(&pool).await.unwrap(), "alice's VC");
+    }
+}
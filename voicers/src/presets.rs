@@ -0,0 +1,157 @@
+use crate::discord_api::{ChannelInfo, VoiceSettings};
+use crate::error::VoicersError;
+use crate::naming;
+use crate::storage::Preset;
+use poise::serenity_prelude as serenity;
+
+// Saving a temp VC's setup and getting it back for /createvc preset:<name>
+
+// Autocomplete can't show more than this anyway
+pub const MAX_PRESETS: usize = 25;
+
+// Saving, deleting and /createvc all go through this so a name typed one way finds the same preset
+pub fn normalize_name(name: &str) -> Result<String, VoicersError> {
+    let name = naming::sanitize(name);
+    if name.is_empty() {
+        return Err(VoicersError::Validation(
+            "Preset names can't be empty".to_string(),
+        ));
+    }
+    Ok(name)
+}
+
+// Read the setup back out of the channel's permission overwrites
+// The owner and anything with MANAGE_CHANNELS (the bot, moderator roles) aren't invitees
+pub fn capture(
+    name: &str,
+    channel: &ChannelInfo,
+    guild_id: serenity::GuildId,
+    owner_id: serenity::UserId,
+) -> Preset {
+    let everyone = guild_id.everyone_role();
+    let mut vc_type = "public";
+    let mut invited_users = Vec::new();
+    let mut invited_roles = Vec::new();
+
+    for overwrite in &channel.permission_overwrites {
+        match overwrite.kind {
+            serenity::PermissionOverwriteType::Role(role_id) if role_id == everyone => {
+                vc_type = if overwrite.deny.view_channel() {
+                    "private"
+                } else {
+                    "public"
+                };
+            }
+            _ if overwrite.allow.manage_channels() || !overwrite.allow.view_channel() => {}
+            serenity::PermissionOverwriteType::Member(user_id) if user_id != owner_id => {
+                invited_users.push(user_id.to_string());
+            }
+            serenity::PermissionOverwriteType::Role(role_id) => {
+                invited_roles.push(role_id.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    Preset {
+        name: name.to_string(),
+        vc_name: channel.name.clone(),
+        vc_type: vc_type.to_string(),
+        invited_users: invited_users.join(","),
+        invited_roles: invited_roles.join(","),
+        user_limit: channel.settings.user_limit.map(i64::from),
+        bitrate: channel.settings.bitrate.map(i64::from),
+    }
+}
+
+fn ids(list: &str) -> impl Iterator<Item = u64> + '_ {
+    list.split(',').filter_map(|id| id.parse().ok())
+}
+
+pub fn invited_users(preset: &Preset) -> Vec<serenity::UserId> {
+    ids(&preset.invited_users)
+        .map(serenity::UserId::new)
+        .collect()
+}
+
+pub fn invited_roles(preset: &Preset) -> Vec<serenity::RoleId> {
+    ids(&preset.invited_roles)
+        .map(serenity::RoleId::new)
+        .collect()
+}
+
+pub fn settings(preset: &Preset) -> VoiceSettings {
+    VoiceSettings {
+        user_limit: preset
+            .user_limit
+            .and_then(|limit| u32::try_from(limit).ok()),
+        bitrate: preset
+            .bitrate
+            .and_then(|bitrate| u32::try_from(bitrate).ok()),
+        ..VoiceSettings::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{BOT, GUILD};
+
+    fn overwrite(
+        allow: serenity::Permissions,
+        deny: serenity::Permissions,
+        kind: serenity::PermissionOverwriteType,
+    ) -> serenity::PermissionOverwrite {
+        serenity::PermissionOverwrite { allow, deny, kind }
+    }
+
+    #[test]
+    fn captures_what_createvc_set_up() {
+        use poise::serenity_prelude::PermissionOverwriteType::{Member, Role};
+        let view = serenity::Permissions::VIEW_CHANNEL;
+        let manage = view | serenity::Permissions::MANAGE_CHANNELS;
+        let none = serenity::Permissions::empty();
+        let owner = serenity::UserId::new(10);
+
+        let channel = ChannelInfo {
+            id: serenity::ChannelId::new(1000),
+            name: "movie night".to_string(),
+            kind: serenity::ChannelType::Voice,
+            parent_id: None,
+            permission_overwrites: vec![
+                overwrite(view, none, Member(serenity::UserId::new(11))),
+                overwrite(view, none, Role(serenity::RoleId::new(5))),
+                // A moderator role
+                overwrite(manage, none, Role(serenity::RoleId::new(6))),
+                overwrite(none, view, Role(GUILD.everyone_role())),
+                overwrite(view, none, Member(owner)),
+                overwrite(manage, none, Member(BOT)),
+            ],
+            settings: VoiceSettings {
+                user_limit: Some(4),
+                bitrate: Some(96000),
+                ..VoiceSettings::default()
+            },
+        };
+
+        let preset = capture("movies", &channel, GUILD, owner);
+        assert_eq!(preset.vc_name, "movie night");
+        assert_eq!(preset.vc_type, "private");
+        assert_eq!(invited_users(&preset), vec![serenity::UserId::new(11)]);
+        assert_eq!(invited_roles(&preset), vec![serenity::RoleId::new(5)]);
+        assert_eq!(settings(&preset), channel.settings);
+    }
+
+    #[test]
+    fn names_are_normalized_the_same_way_everywhere() {
+        assert_eq!(normalize_name("  movie\tnight ").unwrap(), "movie night");
+        assert_eq!(
+            normalize_name("movie night").unwrap(),
+            normalize_name(" movie  night").unwrap()
+        );
+        assert!(matches!(
+            normalize_name(" \n "),
+            Err(VoicersError::Validation(_))
+        ));
+    }
+}
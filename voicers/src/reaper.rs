@@ -0,0 +1,244 @@
+use crate::clock::Clock;
+use crate::config::{Config, Feature, OrphanPolicy};
+use crate::discord_api::DiscordApi;
+use crate::error::VoicersError;
+use crate::health::Health;
+use crate::{lifecycle, metrics, storage};
+use poise::serenity_prelude as serenity;
+use sqlx::SqlitePool;
+use std::{sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+// Everything the reaper needs from the config, read once when it starts
+#[derive(Clone, Debug)]
+pub struct ReaperSettings {
+    pub timeout: u64,
+    pub sync: bool,
+    pub orphan_policy: OrphanPolicy,
+    pub categories: Vec<serenity::ChannelId>,
+}
+
+impl ReaperSettings {
+    pub fn from_config(config: &Config) -> Self {
+        ReaperSettings {
+            timeout: config.voice.global_timeout,
+            sync: config.is_feature_enabled(Feature::VcSync),
+            orphan_policy: config.voice.orphan_policy,
+            categories: config
+                .misc
+                .vc_categories()
+                .into_iter()
+                .map(serenity::ChannelId::new)
+                .collect(),
+        }
+    }
+}
+
+pub enum CustomEvent {
+    PollingDeleteVC { vc_id: i64, guild_id: i64 },
+}
+
+pub async fn start_polling(
+    pool: Arc<SqlitePool>,
+    sender: tokio::sync::mpsc::Sender<CustomEvent>,
+    api: Arc<dyn DiscordApi>,
+    clock: Arc<dyn Clock>,
+    health: Arc<Health>,
+    settings: ReaperSettings,
+    shutdown: CancellationToken,
+) -> Result<(), VoicersError> {
+    debug!("Starting polling task");
+
+    // Pick up anything left behind by a crash before the first pass
+    reconcile_orphans(api.as_ref(), &pool, clock.now(), &settings).await;
+
+    let delay = Duration::from_secs(settings.timeout);
+    let mut loop_counter = 0;
+
+    loop {
+        // Sleep for the delay
+        // Returning drops the sender, which lets the delete handler drain and stop
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.cancelled() => {
+                debug!("Polling task stopping for shutdown");
+                return Ok(());
+            }
+        }
+        debug!("Polling task woke up");
+        let loop_timer = metrics::REAPER_LOOP_DURATION.start_timer();
+        loop_counter += 1;
+
+        if loop_counter == lifecycle::SYNC_INTERVAL && settings.sync {
+            debug!("Syncing with the database");
+
+            // Sync the DB with the guilds and VCs
+            lifecycle::sync_user_counts(api.as_ref(), &pool).await?;
+
+            reconcile_orphans(api.as_ref(), &pool, clock.now(), &settings).await;
+        }
+        if loop_counter >= lifecycle::SYNC_INTERVAL {
+            loop_counter = 0;
+        }
+
+        // Process voice channels for inactivity
+        let now = clock.now();
+        for row in lifecycle::expired_channels(&pool, now, settings.timeout as i64).await? {
+            info!(
+                guild_id = row.guild_id,
+                vc_id = row.vc_id,
+                "VC has been vacant for longer than voice_timeout, deleting it"
+            );
+            delete_voice_channel(&sender, row.vc_id, row.guild_id).await?;
+        }
+
+        update_active_channels(&pool).await;
+        loop_timer.observe_duration();
+        health.record_poll(now);
+    }
+}
+
+// Look for temp VCs the database doesn't know about in every guild we're in
+// Failures are only logged, a guild we can't read shouldn't stop the reaper
+async fn reconcile_orphans(
+    api: &dyn DiscordApi,
+    pool: &SqlitePool,
+    now: i64,
+    settings: &ReaperSettings,
+) {
+    if settings.orphan_policy == OrphanPolicy::Ignore {
+        return;
+    }
+    let bot_user_id = api.current_user_id();
+
+    for guild_id in api.guild_ids() {
+        // Plus the category we made for this guild, if we had to make one
+        let mut categories = settings.categories.clone();
+        match storage::guild_category(pool, guild_id.get() as i64).await {
+            Ok(Some(category)) => categories.push(serenity::ChannelId::new(category as u64)),
+            Ok(None) => {}
+            Err(e) => {
+                warn!(guild_id = guild_id.get(), error = %e, "Unable to read the guild's category")
+            }
+        }
+        // Without a category there's no telling our channels apart from anyone else's
+        if categories.is_empty() {
+            continue;
+        }
+
+        match lifecycle::reconcile_orphans(
+            api,
+            pool,
+            guild_id,
+            &categories,
+            bot_user_id,
+            settings.orphan_policy,
+            now,
+        )
+        .await
+        {
+            Ok(report) if report.adopted + report.deleted > 0 => {
+                info!(
+                    guild_id = guild_id.get(),
+                    adopted = report.adopted,
+                    deleted = report.deleted,
+                    "Reconciled orphaned voice channels"
+                );
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!(guild_id = guild_id.get(), error = %e, "Unable to check for orphaned VCs");
+            }
+        }
+    }
+}
+
+// Refresh the per guild gauge from the database, which is the source of truth for tracked VCs
+async fn update_active_channels(pool: &SqlitePool) {
+    let counts =
+        sqlx::query_as::<_, (i64, i64)>("SELECT guild_id, COUNT(*) FROM users GROUP BY guild_id")
+            .fetch_all(pool)
+            .await;
+
+    match counts {
+        Ok(counts) => {
+            // Reset first so guilds without any VCs left drop off instead of sticking around
+            metrics::ACTIVE_CHANNELS.reset();
+            for (guild_id, count) in counts {
+                metrics::ACTIVE_CHANNELS
+                    .with_label_values(&[&guild_id.to_string()])
+                    .set(count);
+            }
+        }
+        Err(e) => error!("Failed to count active voice channels: {:?}", e),
+    }
+}
+
+async fn delete_voice_channel(
+    sender: &tokio::sync::mpsc::Sender<CustomEvent>,
+    vc_id: i64,
+    guild_id: i64,
+) -> Result<(), VoicersError> {
+    sender
+        .send(CustomEvent::PollingDeleteVC { vc_id, guild_id })
+        .await
+        .map_err(|_| VoicersError::NotFound("The delete handler is not running".to_string()))
+}
+
+pub async fn handle_polling_delete_event(
+    receiver: Arc<tokio::sync::Mutex<tokio::sync::mpsc::Receiver<CustomEvent>>>,
+    api: Arc<dyn DiscordApi>,
+    pool: Arc<SqlitePool>,
+) -> Result<(), VoicersError> {
+    let mut receiver = receiver.lock().await;
+    while let Some(event) = receiver.recv().await {
+        match event {
+            CustomEvent::PollingDeleteVC { vc_id, guild_id } => {
+                lifecycle::reap_channel(api.as_ref(), &pool, vc_id, guild_id).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::health::TaskStatus;
+    use crate::testing::Harness;
+    use std::time::Duration;
+
+    // The lifecycle scenarios live with the Harness in testing.rs, these are about the task itself
+
+    #[tokio::test]
+    async fn every_pass_is_recorded_with_the_clock_time() {
+        let harness = Harness::new(300).await;
+        assert_eq!(harness.voicers.health.last_poll(), None);
+
+        harness.advance(300).await;
+        assert_eq!(harness.voicers.health.last_poll(), Some(300));
+        harness.advance(299).await;
+        assert_eq!(harness.voicers.health.last_poll(), Some(300));
+        harness.advance(1).await;
+        assert_eq!(harness.voicers.health.last_poll(), Some(600));
+
+        harness.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_the_reaper_and_its_delete_handler() {
+        let harness = Harness::new(300).await;
+        harness.advance(300).await;
+        harness.shutdown().await;
+
+        let tasks = harness.voicers.health.tasks();
+        assert_eq!(tasks["reaper"].status, TaskStatus::Stopped);
+        assert_eq!(tasks["reaper_delete"].status, TaskStatus::Stopped);
+        assert_eq!(tasks["reaper"].since, 300);
+
+        // Nothing polls anymore, so the health check notices once enough time goes by
+        tokio::time::advance(Duration::from_secs(300 * 4)).await;
+        assert_eq!(harness.voicers.health.last_poll(), Some(300));
+        assert!(harness.voicers.health.reaper_stalled(harness.now(), 300));
+    }
+}
@@ -0,0 +1,44 @@
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+// How long the reaper gets to finish deleting channels before we give up on it
+pub const DRAIN_TIMEOUT_SECS: u64 = 30;
+
+// Cancel the token on the first SIGINT or SIGTERM
+// Everything that needs to stop cleanly watches the token instead of the signals
+pub async fn listen_for_signals(shutdown: CancellationToken) {
+    tokio::select! {
+        _ = ctrl_c() => info!("Received SIGINT, shutting down"),
+        _ = terminate() => info!("Received SIGTERM, shutting down"),
+        _ = shutdown.cancelled() => return,
+    }
+    shutdown.cancel();
+}
+
+async fn ctrl_c() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!("Failed to listen for SIGINT: {}", e);
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(unix)]
+async fn terminate() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            sigterm.recv().await;
+        }
+        Err(e) => {
+            error!("Failed to listen for SIGTERM: {}", e);
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+// Windows has no SIGTERM, ctrl_c covers it there
+#[cfg(not(unix))]
+async fn terminate() {
+    std::future::pending::<()>().await;
+}
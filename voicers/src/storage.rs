@@ -0,0 +1,525 @@
+use sqlx::{SqliteConnection, SqlitePool};
+use std::fs;
+use std::path::Path;
+use tracing::warn;
+
+// Everything that touches the VC tables lives here so the schema is only spelled out once
+
+// A VC setup a member saved to recreate later with /createvc preset:<name>
+// Invitees are comma separated IDs, there's never more than a handful
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct Preset {
+    pub name: String,
+    pub vc_name: String,
+    pub vc_type: String,
+    pub invited_users: String,
+    pub invited_roles: String,
+    pub user_limit: Option<i64>,
+    pub bitrate: Option<i64>,
+}
+
+//database struct
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub vc_id: i64,
+    pub guild_id: i64,
+    pub last_update: i64,
+    pub user_count: i32,
+    // Who ran /createvc, None for channels adopted after a crash
+    pub owner_id: Option<i64>,
+}
+
+// Connect to the database file, creating it and the tables the first time
+pub async fn open(db_path: &str) -> Result<SqlitePool, sqlx::Error> {
+    initialize_database(db_path)?;
+    let pool = SqlitePool::connect(&format!("sqlite:{}", db_path)).await?;
+    init_schema(&pool).await?;
+    // Nothing is being created while the bot starts, a crash halfway through a create would
+    // otherwise leave that member unable to create another
+    sqlx::query("UPDATE user_cooldowns SET creating = 0")
+        .execute(&pool)
+        .await?;
+    Ok(pool)
+}
+
+fn initialize_database(db_path: &str) -> std::io::Result<()> {
+    if !Path::new(db_path).exists() {
+        warn!("Database does not exist, creating it...");
+        // Create an empty file to initialize the database
+        fs::File::create(db_path)?;
+    }
+    Ok(())
+}
+
+pub async fn init_schema(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let create_table_query = r#"
+    CREATE TABLE IF NOT EXISTS users (
+        vc_id INTEGER PRIMARY KEY,
+        guild_id INTEGER NOT NULL,
+        last_update INTEGER NOT NULL,
+        user_count INTEGER NOT NULL,
+        owner_id INTEGER
+        );
+    "#;
+    sqlx::query(create_table_query).execute(pool).await?;
+
+    // owner_id came later, databases from before then need the column added
+    add_column_if_missing(pool, "users", "owner_id", "INTEGER").await?;
+
+    // Per guild settings changed through commands, rather than the config file
+    let create_guild_settings_query = r#"
+    CREATE TABLE IF NOT EXISTS guild_settings (
+        guild_id INTEGER PRIMARY KEY,
+        name_template TEXT,
+        name_counter INTEGER NOT NULL DEFAULT 0,
+        category_id INTEGER
+        );
+    "#;
+    sqlx::query(create_guild_settings_query)
+        .execute(pool)
+        .await?;
+    add_column_if_missing(pool, "guild_settings", "category_id", "INTEGER").await?;
+
+    let create_presets_query = r#"
+    CREATE TABLE IF NOT EXISTS presets (
+        guild_id INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        vc_name TEXT NOT NULL,
+        vc_type TEXT NOT NULL,
+        invited_users TEXT NOT NULL,
+        invited_roles TEXT NOT NULL,
+        user_limit INTEGER,
+        bitrate INTEGER,
+        PRIMARY KEY (guild_id, user_id, name)
+        );
+    "#;
+    sqlx::query(create_presets_query).execute(pool).await?;
+
+    // When each member last created a VC, for the cooldown, and whether one is being created now
+    // previous_created is what last_created goes back to when that create fails, NULL for none
+    let create_user_cooldowns_query = r#"
+    CREATE TABLE IF NOT EXISTS user_cooldowns (
+        guild_id INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        last_created INTEGER NOT NULL,
+        previous_created INTEGER,
+        creating INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (guild_id, user_id)
+        );
+    "#;
+    sqlx::query(create_user_cooldowns_query)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// SQLite has no ADD COLUMN IF NOT EXISTS, so look before altering
+async fn add_column_if_missing(
+    pool: &SqlitePool,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), sqlx::Error> {
+    let exists: bool =
+        sqlx::query_scalar("SELECT COUNT(*) > 0 FROM pragma_table_info(?) WHERE name = ?")
+            .bind(table)
+            .bind(column)
+            .fetch_one(pool)
+            .await?;
+    if !exists {
+        warn!("Adding the {} column to the {} table", column, table);
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+// Start tracking a freshly created VC, nobody is in it yet
+pub async fn insert_channel(
+    pool: &SqlitePool,
+    vc_id: i64,
+    guild_id: i64,
+    owner_id: Option<i64>,
+    now: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO users (vc_id, guild_id, last_update, user_count, owner_id) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(vc_id)
+    .bind(guild_id)
+    .bind(now)
+    .bind(0)
+    .bind(owner_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn remove_channel(pool: &SqlitePool, vc_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM users WHERE vc_id = ?")
+        .bind(vc_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn tracked_channel_ids(
+    pool: &SqlitePool,
+    guild_id: i64,
+) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT vc_id FROM users WHERE guild_id = ?")
+        .bind(guild_id)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn tracked_channels(pool: &SqlitePool) -> Result<Vec<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(
+        "SELECT vc_id, guild_id, last_update, user_count, owner_id FROM users",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn tracked_channel(pool: &SqlitePool, vc_id: i64) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(
+        "SELECT vc_id, guild_id, last_update, user_count, owner_id FROM users WHERE vc_id = ?",
+    )
+    .bind(vc_id)
+    .fetch_optional(pool)
+    .await
+}
+
+// Someone joined, channels we don't track are left alone by the WHERE
+pub async fn user_joined(pool: &SqlitePool, vc_id: i64, now: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET last_update = ?, user_count = user_count + 1 WHERE vc_id = ?")
+        .bind(now)
+        .bind(vc_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn user_left(pool: &SqlitePool, vc_id: i64, now: i64) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE users SET last_update = ?, user_count = CASE WHEN user_count - 1 < 0 THEN 0 ELSE user_count - 1 END WHERE vc_id = ?"
+    )
+    .bind(now)
+    .bind(vc_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_user_count(
+    pool: &SqlitePool,
+    vc_id: i64,
+    user_count: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET user_count = ? WHERE vc_id = ?")
+        .bind(user_count)
+        .bind(vc_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// The template a guild picked for VC names, None means use the one from the config
+pub async fn name_template(
+    pool: &SqlitePool,
+    guild_id: i64,
+) -> Result<Option<String>, sqlx::Error> {
+    let template: Option<Option<String>> =
+        sqlx::query_scalar("SELECT name_template FROM guild_settings WHERE guild_id = ?")
+            .bind(guild_id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(template.flatten())
+}
+
+pub async fn set_name_template(
+    pool: &SqlitePool,
+    guild_id: i64,
+    template: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO guild_settings (guild_id, name_template) VALUES (?, ?) ON CONFLICT(guild_id) DO UPDATE SET name_template = excluded.name_template",
+    )
+    .bind(guild_id)
+    .bind(template)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Bump the guild's {counter} and hand back the new value, starting at 1
+pub async fn next_name_counter(pool: &SqlitePool, guild_id: i64) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "INSERT INTO guild_settings (guild_id, name_counter) VALUES (?, 1) ON CONFLICT(guild_id) DO UPDATE SET name_counter = name_counter + 1 RETURNING name_counter",
+    )
+    .bind(guild_id)
+    .fetch_one(pool)
+    .await
+}
+
+// The category the bot made for the guild's temp VCs, if it had to make one
+pub async fn guild_category(pool: &SqlitePool, guild_id: i64) -> Result<Option<i64>, sqlx::Error> {
+    let category: Option<Option<i64>> =
+        sqlx::query_scalar("SELECT category_id FROM guild_settings WHERE guild_id = ?")
+            .bind(guild_id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(category.flatten())
+}
+
+pub async fn set_guild_category(
+    pool: &SqlitePool,
+    guild_id: i64,
+    category_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO guild_settings (guild_id, category_id) VALUES (?, ?) ON CONFLICT(guild_id) DO UPDATE SET category_id = excluded.category_id",
+    )
+    .bind(guild_id)
+    .bind(category_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// The counts take a connection so they can run inside claim_create_slot's transaction
+pub async fn guild_channel_count(
+    conn: &mut SqliteConnection,
+    guild_id: i64,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE guild_id = ?")
+        .bind(guild_id)
+        .fetch_one(conn)
+        .await
+}
+
+pub async fn owned_channel_count(
+    conn: &mut SqliteConnection,
+    guild_id: i64,
+    owner_id: i64,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE guild_id = ? AND owner_id = ?")
+        .bind(guild_id)
+        .bind(owner_id)
+        .fetch_one(conn)
+        .await
+}
+
+// Creates that claimed their slot but haven't finished or been released yet
+pub async fn pending_creates(
+    conn: &mut SqliteConnection,
+    guild_id: i64,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM user_cooldowns WHERE guild_id = ? AND creating = 1")
+        .bind(guild_id)
+        .fetch_one(conn)
+        .await
+}
+
+// Newest first, so /vc knock by owner picks the one they made last
+pub async fn owned_channels(
+    pool: &SqlitePool,
+    guild_id: i64,
+    owner_id: i64,
+) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT vc_id FROM users WHERE guild_id = ? AND owner_id = ? ORDER BY vc_id DESC",
+    )
+    .bind(guild_id)
+    .bind(owner_id)
+    .fetch_all(pool)
+    .await
+}
+
+// Mark a create as started for this member, unless one still is or their cooldown hasn't run out
+// It writes before anything is read, so the transaction it's in holds SQLite's write lock
+// from the start and a second create has to wait for the first to commit
+pub async fn claim_create_slot(
+    conn: &mut SqliteConnection,
+    guild_id: i64,
+    user_id: i64,
+    now: i64,
+    cooldown: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "INSERT INTO user_cooldowns (guild_id, user_id, last_created, creating) VALUES (?, ?, ?, 1)
+        ON CONFLICT (guild_id, user_id) DO UPDATE
+        SET previous_created = last_created, last_created = excluded.last_created, creating = 1
+        WHERE creating = 0 AND last_created <= ?",
+    )
+    .bind(guild_id)
+    .bind(user_id)
+    .bind(now)
+    .bind(now - cooldown)
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+// When the member last created a VC and whether they're creating one right now
+pub async fn create_slot(
+    conn: &mut SqliteConnection,
+    guild_id: i64,
+    user_id: i64,
+) -> Result<Option<(i64, bool)>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT last_created, creating FROM user_cooldowns WHERE guild_id = ? AND user_id = ?",
+    )
+    .bind(guild_id)
+    .bind(user_id)
+    .fetch_optional(conn)
+    .await
+}
+
+// The channel is there, the cooldown runs from when it was claimed
+pub async fn finish_create(
+    pool: &SqlitePool,
+    guild_id: i64,
+    user_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE user_cooldowns SET creating = 0 WHERE guild_id = ? AND user_id = ?")
+        .bind(guild_id)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// The create failed, so it doesn't count towards the cooldown either
+// A member whose first create failed is back to never having created one
+pub async fn release_create_slot(
+    pool: &SqlitePool,
+    guild_id: i64,
+    user_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "DELETE FROM user_cooldowns
+        WHERE guild_id = ? AND user_id = ? AND previous_created IS NULL",
+    )
+    .bind(guild_id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    sqlx::query(
+        "UPDATE user_cooldowns SET last_created = previous_created, creating = 0
+        WHERE guild_id = ? AND user_id = ?",
+    )
+    .bind(guild_id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Saving under a name that already exists replaces the old preset
+pub async fn save_preset(
+    pool: &SqlitePool,
+    guild_id: i64,
+    user_id: i64,
+    preset: &Preset,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO presets (guild_id, user_id, name, vc_name, vc_type, invited_users, invited_roles, user_limit, bitrate) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(guild_id)
+    .bind(user_id)
+    .bind(&preset.name)
+    .bind(&preset.vc_name)
+    .bind(&preset.vc_type)
+    .bind(&preset.invited_users)
+    .bind(&preset.invited_roles)
+    .bind(preset.user_limit)
+    .bind(preset.bitrate)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn preset(
+    pool: &SqlitePool,
+    guild_id: i64,
+    user_id: i64,
+    name: &str,
+) -> Result<Option<Preset>, sqlx::Error> {
+    sqlx::query_as::<_, Preset>(
+        "SELECT name, vc_name, vc_type, invited_users, invited_roles, user_limit, bitrate FROM presets WHERE guild_id = ? AND user_id = ? AND name = ?",
+    )
+    .bind(guild_id)
+    .bind(user_id)
+    .bind(name)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn preset_names(
+    pool: &SqlitePool,
+    guild_id: i64,
+    user_id: i64,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT name FROM presets WHERE guild_id = ? AND user_id = ? ORDER BY name")
+        .bind(guild_id)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+}
+
+// Whether there was anything to delete
+pub async fn delete_preset(
+    pool: &SqlitePool,
+    guild_id: i64,
+    user_id: i64,
+    name: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM presets WHERE guild_id = ? AND user_id = ? AND name = ?")
+        .bind(guild_id)
+        .bind(user_id)
+        .bind(name)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_pool;
+
+    #[tokio::test]
+    async fn old_databases_get_the_owner_column() {
+        let pool = test_pool(false).await;
+        sqlx::query(
+            "CREATE TABLE users (vc_id INTEGER PRIMARY KEY, guild_id INTEGER NOT NULL, last_update INTEGER NOT NULL, user_count INTEGER NOT NULL)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO users VALUES (1, 2, 3, 0)")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        init_schema(&pool).await.unwrap();
+        // Running it again on a migrated database has nothing left to do
+        init_schema(&pool).await.unwrap();
+        insert_channel(&pool, 4, 2, Some(10), 5).await.unwrap();
+
+        assert_eq!(
+            tracked_channel(&pool, 1).await.unwrap().unwrap().owner_id,
+            None
+        );
+        assert_eq!(
+            tracked_channel(&pool, 4).await.unwrap().unwrap().owner_id,
+            Some(10)
+        );
+    }
+}
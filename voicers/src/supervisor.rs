@@ -0,0 +1,272 @@
+use crate::discord::Error;
+use crate::health::{Health, TaskReport, TaskStatus};
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{error, info, warn};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+// A task that ran at least this long before failing starts over at INITIAL_BACKOFF
+const HEALTHY_RUN: Duration = Duration::from_secs(600);
+
+// Keeps the long running background tasks alive
+// Each task is started at most once by name, restarted with backoff when it errors or panics,
+// and reports its status into Health so /readyz can see it
+pub struct Supervisor {
+    tasks: TaskTracker,
+    shutdown: CancellationToken,
+    health: Arc<Health>,
+    started: Mutex<HashSet<&'static str>>,
+}
+
+impl Supervisor {
+    pub fn new(health: Arc<Health>, shutdown: CancellationToken) -> Self {
+        Supervisor {
+            tasks: TaskTracker::new(),
+            shutdown,
+            health,
+            started: Mutex::new(HashSet::new()),
+        }
+    }
+
+    // Start a supervised task unless one with this name is already running
+    // The factory is called again for every restart, so it has to hand out fresh futures
+    // Returns false if the task was already started
+    pub fn spawn<F, Fut>(&self, name: &'static str, factory: F) -> bool
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        if !self.started.lock().unwrap().insert(name) {
+            info!(
+                task = name,
+                "Task is already running, not starting it again"
+            );
+            return false;
+        }
+
+        let shutdown = self.shutdown.clone();
+        let health = self.health.clone();
+
+        self.tasks.spawn(async move {
+            let mut restarts = 0;
+            let mut backoff = INITIAL_BACKOFF;
+
+            loop {
+                report(&health, name, TaskStatus::Running, restarts, None);
+                let started_at = Instant::now();
+
+                // Run each attempt in its own task so a panic ends up in the JoinError
+                let outcome = match tokio::spawn(factory()).await {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some(e.to_string()),
+                    Err(e) if e.is_panic() => Some(format!("panicked: {}", panic_message(e))),
+                    Err(e) => Some(e.to_string()),
+                };
+
+                let Some(failure) = outcome else {
+                    info!(task = name, "Task finished");
+                    report(&health, name, TaskStatus::Stopped, restarts, None);
+                    return;
+                };
+
+                if shutdown.is_cancelled() {
+                    warn!(task = name, error = %failure, "Task failed during shutdown");
+                    report(&health, name, TaskStatus::Stopped, restarts, Some(failure));
+                    return;
+                }
+
+                if started_at.elapsed() >= HEALTHY_RUN {
+                    backoff = INITIAL_BACKOFF;
+                }
+                restarts += 1;
+                error!(
+                    task = name,
+                    error = %failure,
+                    restarts,
+                    backoff_secs = backoff.as_secs(),
+                    "Task failed, restarting"
+                );
+                report(
+                    &health,
+                    name,
+                    TaskStatus::Restarting,
+                    restarts,
+                    Some(failure),
+                );
+
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => {}
+                    _ = shutdown.cancelled() => {
+                        report(&health, name, TaskStatus::Stopped, restarts, None);
+                        return;
+                    }
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        });
+
+        true
+    }
+
+    // Wait for every supervised task to stop, giving up after the timeout
+    // Returns false if something was still running
+    pub async fn wait(&self, timeout: Duration) -> bool {
+        self.tasks.close();
+        tokio::time::timeout(timeout, self.tasks.wait())
+            .await
+            .is_ok()
+    }
+}
+
+fn report(
+    health: &Health,
+    name: &'static str,
+    status: TaskStatus,
+    restarts: u32,
+    last_error: Option<String>,
+) {
+    health.set_task(
+        name,
+        TaskReport {
+            status,
+            restarts,
+            last_error,
+            since: health.now(),
+        },
+    );
+}
+
+fn panic_message(e: tokio::task::JoinError) -> String {
+    let panic = e.into_panic();
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::VoicersError;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn supervisor() -> (Supervisor, Arc<Health>, CancellationToken) {
+        let health = Arc::new(Health::new());
+        let shutdown = CancellationToken::new();
+        (
+            Supervisor::new(health.clone(), shutdown.clone()),
+            health,
+            shutdown,
+        )
+    }
+
+    fn failing() -> Result<(), Error> {
+        Err(VoicersError::NotFound("gone".to_string()))
+    }
+
+    // With time paused, sleeping here lets the runtime jump from one backoff to the next
+    async fn sleep_secs(seconds: f64) {
+        tokio::time::sleep(Duration::from_secs_f64(seconds)).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failing_task_is_restarted_with_growing_backoff() {
+        let (supervisor, health, shutdown) = supervisor();
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let start = Instant::now();
+
+        let recorded = attempts.clone();
+        assert!(supervisor.spawn("flaky", move || {
+            recorded.lock().unwrap().push(start.elapsed().as_secs());
+            async { failing() }
+        }));
+        sleep_secs(20.0).await;
+
+        assert_eq!(*attempts.lock().unwrap(), vec![0, 1, 3, 7, 15]);
+        let report = health.tasks()["flaky"].clone();
+        assert_eq!(report.status, TaskStatus::Restarting);
+        assert_eq!(report.restarts, 5);
+        assert!(report.last_error.unwrap().contains("gone"));
+
+        shutdown.cancel();
+        assert!(supervisor.wait(Duration::from_secs(1)).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn panicking_task_is_restarted_and_reported() {
+        let (supervisor, health, shutdown) = supervisor();
+        let attempts = Arc::new(AtomicU32::new(0));
+
+        let counter = attempts.clone();
+        supervisor.spawn("panicky", move || {
+            let attempt = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if attempt == 0 {
+                    panic!("boom");
+                }
+                std::future::pending::<Result<(), Error>>().await
+            }
+        });
+
+        sleep_secs(0.5).await;
+        let report = health.tasks()["panicky"].clone();
+        assert_eq!(report.status, TaskStatus::Restarting);
+        assert_eq!(report.last_error.as_deref(), Some("panicked: boom"));
+
+        sleep_secs(1.0).await;
+        let report = health.tasks()["panicky"].clone();
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert_eq!(report.status, TaskStatus::Running);
+        assert_eq!(report.restarts, 1);
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_same_name_is_only_started_once() {
+        let (supervisor, _health, shutdown) = supervisor();
+        let attempts = Arc::new(AtomicU32::new(0));
+
+        for _ in 0..2 {
+            let counter = attempts.clone();
+            supervisor.spawn("reaper", move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                std::future::pending::<Result<(), Error>>()
+            });
+        }
+        sleep_secs(1.0).await;
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert!(!supervisor.spawn("reaper", || async { Ok(()) }));
+        shutdown.cancel();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_stops_restarts() {
+        let (supervisor, health, shutdown) = supervisor();
+        let attempts = Arc::new(AtomicU32::new(0));
+
+        let counter = attempts.clone();
+        supervisor.spawn("flaky", move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { failing() }
+        });
+        // First attempt failed, now waiting out the backoff
+        sleep_secs(0.5).await;
+        shutdown.cancel();
+        assert!(supervisor.wait(Duration::from_secs(1)).await);
+        sleep_secs(60.0).await;
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert_eq!(health.tasks()["flaky"].status, TaskStatus::Stopped);
+    }
+}
@@ -0,0 +1,744 @@
+use crate::clock::Clock;
+use crate::config::{Config, OrphanPolicy};
+use crate::discord_api::{
+    ChannelEdit, ChannelInfo, DiscordApi, KnockPress, KnockRequest, MemberInfo, NewVoiceChannel,
+    VoiceSettings,
+};
+use crate::engine::{self, Voicers};
+use crate::health::Health;
+use crate::knock::PendingKnocks;
+use crate::lifecycle::{CreateRequest, VcType};
+use crate::supervisor::Supervisor;
+use crate::{lifecycle, storage};
+use async_trait::async_trait;
+use poise::serenity_prelude as serenity;
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+// In-memory databases are per connection, so the pool must only ever have one
+pub async fn test_pool(with_schema: bool) -> SqlitePool {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    if with_schema {
+        storage::init_schema(&pool).await.unwrap();
+    }
+    pool
+}
+
+// Follows tokio's clock, which only moves when a paused runtime lets it
+// Starts at 0 so timestamps in tests read as seconds since the test began
+pub struct PausedClock {
+    start: tokio::time::Instant,
+}
+
+impl PausedClock {
+    pub fn new() -> Self {
+        PausedClock {
+            start: tokio::time::Instant::now(),
+        }
+    }
+}
+
+impl Default for PausedClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for PausedClock {
+    fn now(&self) -> i64 {
+        self.start.elapsed().as_secs() as i64
+    }
+}
+
+// A single guild kept in memory, standing in for Discord in tests
+// Guild ids are ignored, everything happens in the one guild
+#[derive(Default)]
+pub struct FakeGuild {
+    state: Mutex<FakeState>,
+}
+
+#[derive(Default)]
+pub struct FakeState {
+    pub channels: BTreeMap<serenity::ChannelId, ChannelInfo>,
+    pub members: BTreeMap<serenity::UserId, MemberInfo>,
+    // Which voice channel each connected member is in
+    pub voice_states: BTreeMap<serenity::UserId, serenity::ChannelId>,
+    pub activities: BTreeMap<serenity::UserId, String>,
+    pub premium_tier: serenity::PremiumTier,
+    pub messages: Vec<(serenity::ChannelId, String)>,
+    // Every delete asked for, including the ones that failed
+    pub delete_requests: Vec<serenity::ChannelId>,
+    pub deleted: Vec<serenity::ChannelId>,
+    // Knock requests in the order they were sent, a message id is the index plus one
+    // The text is replaced once the knock is answered
+    pub knocks: Vec<(serenity::ChannelId, String)>,
+    // Button presses to hand out, once they run out every knock times out
+    pub knock_presses: VecDeque<KnockPress>,
+    // Owners with DMs closed get knocked on in the VC's chat, everyone else in a DM
+    pub dms_closed: bool,
+    pub fail_create: bool,
+    pub fail_delete: bool,
+    next_id: u64,
+}
+
+impl FakeGuild {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn add_channel(&self, channel: ChannelInfo) {
+        self.state().channels.insert(channel.id, channel);
+    }
+
+    pub fn add_member(&self, user_id: u64, name: &str, roles: &[u64]) -> serenity::UserId {
+        let user_id = serenity::UserId::new(user_id);
+        self.state().members.insert(
+            user_id,
+            MemberInfo {
+                name: name.to_string(),
+                nick: None,
+                roles: roles.iter().copied().map(serenity::RoleId::new).collect(),
+            },
+        );
+        user_id
+    }
+
+    // Connect a member to voice, or move them if they're already connected
+    pub fn join(&self, user_id: serenity::UserId, channel_id: serenity::ChannelId) {
+        self.state().voice_states.insert(user_id, channel_id);
+    }
+
+    pub fn leave(&self, user_id: serenity::UserId) {
+        self.state().voice_states.remove(&user_id);
+    }
+
+    pub fn has_channel(&self, channel_id: serenity::ChannelId) -> bool {
+        self.state().channels.contains_key(&channel_id)
+    }
+}
+
+#[async_trait]
+impl DiscordApi for FakeGuild {
+    async fn create_voice_channel(
+        &self,
+        _guild_id: serenity::GuildId,
+        channel: NewVoiceChannel,
+    ) -> Result<serenity::ChannelId, serenity::Error> {
+        let mut state = self.state();
+        if state.fail_create {
+            return Err(serenity::Error::Other("Missing permissions"));
+        }
+
+        // Stay clear of the small ids tests like to use for their own channels
+        state.next_id += 1;
+        let id = serenity::ChannelId::new(1000 + state.next_id);
+        state.channels.insert(
+            id,
+            ChannelInfo {
+                id,
+                name: channel.name,
+                kind: serenity::ChannelType::Voice,
+                parent_id: channel.category,
+                permission_overwrites: channel.permissions,
+                settings: channel.settings,
+            },
+        );
+        Ok(id)
+    }
+
+    async fn create_category(
+        &self,
+        _guild_id: serenity::GuildId,
+        name: &str,
+        permissions: Vec<serenity::PermissionOverwrite>,
+        _audit_reason: &str,
+    ) -> Result<serenity::ChannelId, serenity::Error> {
+        let mut state = self.state();
+        if state.fail_create {
+            return Err(serenity::Error::Other("Missing permissions"));
+        }
+
+        state.next_id += 1;
+        let id = serenity::ChannelId::new(1000 + state.next_id);
+        state.channels.insert(
+            id,
+            ChannelInfo {
+                id,
+                name: name.to_string(),
+                kind: serenity::ChannelType::Category,
+                parent_id: None,
+                permission_overwrites: permissions,
+                settings: VoiceSettings::default(),
+            },
+        );
+        Ok(id)
+    }
+
+    async fn delete_channel(
+        &self,
+        channel_id: serenity::ChannelId,
+        _reason: &str,
+    ) -> Result<(), serenity::Error> {
+        let mut state = self.state();
+        state.delete_requests.push(channel_id);
+        if state.fail_delete {
+            return Err(serenity::Error::Other("Missing permissions"));
+        }
+        if state.channels.remove(&channel_id).is_none() {
+            return Err(serenity::Error::Other("Unknown channel"));
+        }
+
+        // Discord kicks everyone out of a deleted voice channel
+        state
+            .voice_states
+            .retain(|_, channel| *channel != channel_id);
+        state.deleted.push(channel_id);
+        Ok(())
+    }
+
+    async fn edit_channel(
+        &self,
+        channel_id: serenity::ChannelId,
+        edit: ChannelEdit,
+    ) -> Result<(), serenity::Error> {
+        let mut state = self.state();
+        let channel = state
+            .channels
+            .get_mut(&channel_id)
+            .ok_or(serenity::Error::Other("Unknown channel"))?;
+        if let Some(name) = edit.name {
+            channel.name = name;
+        }
+        if let Some(permissions) = edit.permissions {
+            channel.permission_overwrites = permissions;
+        }
+        Ok(())
+    }
+
+    async fn guild_channels(
+        &self,
+        _guild_id: serenity::GuildId,
+    ) -> Result<Vec<ChannelInfo>, serenity::Error> {
+        Ok(self.state().channels.values().cloned().collect())
+    }
+
+    async fn fetch_member(
+        &self,
+        _guild_id: serenity::GuildId,
+        user_id: serenity::UserId,
+    ) -> Result<MemberInfo, serenity::Error> {
+        self.state()
+            .members
+            .get(&user_id)
+            .cloned()
+            .ok_or(serenity::Error::Other("Unknown member"))
+    }
+
+    async fn move_member(
+        &self,
+        _guild_id: serenity::GuildId,
+        user_id: serenity::UserId,
+        channel_id: serenity::ChannelId,
+    ) -> Result<(), serenity::Error> {
+        let mut state = self.state();
+        if !state.channels.contains_key(&channel_id) {
+            return Err(serenity::Error::Other("Unknown channel"));
+        }
+        match state.voice_states.get_mut(&user_id) {
+            Some(channel) => {
+                *channel = channel_id;
+                Ok(())
+            }
+            None => Err(serenity::Error::Other(
+                "Target user is not connected to voice",
+            )),
+        }
+    }
+
+    async fn send_message(
+        &self,
+        channel_id: serenity::ChannelId,
+        content: &str,
+    ) -> Result<(), serenity::Error> {
+        let mut state = self.state();
+        if !state.channels.contains_key(&channel_id) {
+            return Err(serenity::Error::Other("Unknown channel"));
+        }
+        state.messages.push((channel_id, content.to_string()));
+        Ok(())
+    }
+
+    async fn send_knock(
+        &self,
+        owner_id: serenity::UserId,
+        channel_id: serenity::ChannelId,
+        content: &str,
+    ) -> Result<KnockRequest, serenity::Error> {
+        let mut state = self.state();
+        // A DM channel is as good as the owner's own id here
+        let target = if state.dms_closed {
+            channel_id
+        } else {
+            serenity::ChannelId::new(owner_id.get())
+        };
+        if !state.channels.contains_key(&channel_id) {
+            return Err(serenity::Error::Other("Unknown channel"));
+        }
+        state.knocks.push((target, content.to_string()));
+        Ok(KnockRequest {
+            channel_id: target,
+            message_id: serenity::MessageId::new(state.knocks.len() as u64),
+        })
+    }
+
+    async fn next_knock_press(
+        &self,
+        _request: &KnockRequest,
+        timeout: Duration,
+    ) -> Option<KnockPress> {
+        let press = self.state().knock_presses.pop_front();
+        if press.is_none() {
+            tokio::time::sleep(timeout).await;
+        }
+        press
+    }
+
+    async fn close_knock(
+        &self,
+        request: &KnockRequest,
+        content: &str,
+    ) -> Result<(), serenity::Error> {
+        let mut state = self.state();
+        let knock = state
+            .knocks
+            .get_mut(request.message_id.get() as usize - 1)
+            .ok_or(serenity::Error::Other("Unknown message"))?;
+        knock.1 = content.to_string();
+        Ok(())
+    }
+
+    fn current_user_id(&self) -> serenity::UserId {
+        BOT
+    }
+
+    fn guild_ids(&self) -> Vec<serenity::GuildId> {
+        vec![GUILD]
+    }
+
+    fn member_activity(
+        &self,
+        _guild_id: serenity::GuildId,
+        user_id: serenity::UserId,
+    ) -> Option<String> {
+        self.state().activities.get(&user_id).cloned()
+    }
+
+    fn premium_tier(&self, _guild_id: serenity::GuildId) -> serenity::PremiumTier {
+        self.state().premium_tier
+    }
+
+    fn member_voice_channel(
+        &self,
+        _guild_id: serenity::GuildId,
+        user_id: serenity::UserId,
+    ) -> Option<serenity::ChannelId> {
+        self.state().voice_states.get(&user_id).copied()
+    }
+
+    async fn voice_channel_user_count(
+        &self,
+        _guild_id: serenity::GuildId,
+        channel_id: serenity::ChannelId,
+    ) -> Result<usize, serenity::Error> {
+        Ok(self
+            .state()
+            .voice_states
+            .values()
+            .filter(|channel| **channel == channel_id)
+            .count())
+    }
+}
+
+pub const GUILD: serenity::GuildId = serenity::GuildId::new(1);
+pub const BOT: serenity::UserId = serenity::UserId::new(42);
+pub const RULES: &str = "Be nice";
+
+// A plain uncategorized voice channel with no overwrites
+pub fn new_channel() -> NewVoiceChannel {
+    NewVoiceChannel {
+        name: "test_vc".to_string(),
+        category: None,
+        permissions: Vec::new(),
+        settings: VoiceSettings::default(),
+        audit_reason: "test".to_string(),
+    }
+}
+
+// Drive a future without ever letting the runtime go idle
+// The database works on its own thread, and an idle paused runtime skips the clock ahead
+pub async fn busy<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    loop {
+        tokio::select! {
+            biased;
+            output = &mut future => return output,
+            _ = tokio::task::yield_now() => {}
+        }
+    }
+}
+
+// Same idea as busy, for waiting on spawned tasks
+pub async fn settle(condition: impl Fn() -> bool) {
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "timed out waiting for the bot's tasks"
+        );
+        tokio::task::yield_now().await;
+    }
+}
+
+// Runs the bot the way it runs against Discord: the reaper and its delete handler are started
+// by engine::start_reaper and voice states go through engine::voice_state_update,
+// with a FakeGuild for Discord and tokio's clock paused so time only moves when a test says so
+pub struct Harness {
+    pub guild: Arc<FakeGuild>,
+    pub voicers: Voicers,
+}
+
+impl Harness {
+    // Pauses tokio's clock, which needs the current thread runtime #[tokio::test] uses
+    pub async fn new(timeout: u64) -> Self {
+        let guild = Arc::new(FakeGuild::new());
+        // Connecting has to happen before the pause for the same reason as busy
+        let pool = Arc::new(test_pool(true).await);
+        tokio::time::pause();
+
+        let mut config = Config::default();
+        config.voice.global_timeout = timeout;
+        // Nothing to reconcile, so the reaper goes straight to its first sleep
+        config.voice.orphan_policy = OrphanPolicy::Ignore;
+        config.misc.vc_rules = RULES.to_string();
+        let config: &'static Config = Box::leak(Box::new(config));
+
+        let clock = Arc::new(PausedClock::new());
+        let health = Arc::new(Health::with_clock(clock.clone()));
+        let shutdown = CancellationToken::new();
+        let voicers = Voicers {
+            pool,
+            api: guild.clone(),
+            clock,
+            config,
+            supervisor: Arc::new(Supervisor::new(health.clone(), shutdown.clone())),
+            health,
+            shutdown,
+            knocks: PendingKnocks::default(),
+        };
+
+        engine::start_reaper(&voicers);
+        // Both tasks report in as they're spawned, one more turn gets the reaper to its sleep
+        settle(|| voicers.health.tasks().len() == 2).await;
+        tokio::task::yield_now().await;
+
+        Harness { guild, voicers }
+    }
+
+    pub fn now(&self) -> i64 {
+        self.voicers.clock.now()
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.voicers.pool
+    }
+
+    // /createvc for a public VC with a generated name, past the argument parsing
+    pub async fn create_vc(&self, owner: serenity::UserId) -> serenity::ChannelId {
+        let request = CreateRequest {
+            vc_type: VcType::Public,
+            name: None,
+            owner_name: owner.to_string(),
+            invited_users: Vec::new(),
+            invited_roles: Vec::new(),
+            settings: VoiceSettings::default(),
+        };
+        busy(lifecycle::create_vc(
+            self.guild.as_ref(),
+            self.pool(),
+            self.voicers.config,
+            self.voicers.clock.as_ref(),
+            GUILD,
+            owner,
+            request,
+        ))
+        .await
+        .unwrap()
+    }
+
+    // Connect or move a member and deliver the VoiceStateUpdate for it
+    pub async fn join(&self, user_id: serenity::UserId, channel_id: serenity::ChannelId) {
+        let old = self.guild.member_voice_channel(GUILD, user_id);
+        self.guild.join(user_id, channel_id);
+        self.voice_state_update(user_id, old, Some(channel_id))
+            .await;
+    }
+
+    pub async fn leave(&self, user_id: serenity::UserId) {
+        let old = self.guild.member_voice_channel(GUILD, user_id);
+        self.guild.leave(user_id);
+        self.voice_state_update(user_id, old, None).await;
+    }
+
+    async fn voice_state_update(
+        &self,
+        user_id: serenity::UserId,
+        old: Option<serenity::ChannelId>,
+        new: Option<serenity::ChannelId>,
+    ) {
+        busy(engine::voice_state_update(
+            &self.voicers,
+            Some(GUILD),
+            user_id,
+            old,
+            new,
+        ))
+        .await
+        .unwrap();
+    }
+
+    // Let time pass, waiting for every reaper pass that falls inside it to finish
+    // The reaper went to sleep at 0, so it wakes up on every multiple of the timeout
+    pub async fn advance(&self, seconds: u64) {
+        let timeout = self.voicers.config.voice.global_timeout as i64;
+        let target = self.now() + seconds as i64;
+        loop {
+            let next_pass = (self.now() / timeout + 1) * timeout;
+            if next_pass > target {
+                break;
+            }
+            tokio::time::advance(Duration::from_secs((next_pass - self.now()) as u64)).await;
+            self.finish_pass().await;
+        }
+        tokio::time::advance(Duration::from_secs((target - self.now()) as u64)).await;
+    }
+
+    async fn finish_pass(&self) {
+        let last_poll = self.voicers.health.last_poll();
+        let requested = self.guild.state().delete_requests.len();
+        // Yielding never gets the timers looked at, a zero sleep does
+        tokio::time::sleep(Duration::ZERO).await;
+        settle(|| self.voicers.health.last_poll() != last_poll).await;
+
+        // The pass only queued the deletes, wait for the delete handler to get through them
+        while !busy(self.deletes_handled(requested)).await {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    // Every expired channel has had its delete tried, and the ones that worked aren't tracked anymore
+    async fn deletes_handled(&self, requested_before: usize) -> bool {
+        let timeout = self.voicers.config.voice.global_timeout as i64;
+        let expired = lifecycle::expired_channels(self.pool(), self.now(), timeout)
+            .await
+            .unwrap();
+        let tracked = storage::tracked_channels(self.pool()).await.unwrap();
+        let requested = self.guild.state().delete_requests[requested_before..].to_vec();
+        let requested = |vc_id: i64| requested.contains(&serenity::ChannelId::new(vc_id as u64));
+
+        expired.iter().all(|row| requested(row.vc_id))
+            && tracked.iter().all(|row| {
+                !requested(row.vc_id)
+                    || self
+                        .guild
+                        .has_channel(serenity::ChannelId::new(row.vc_id as u64))
+            })
+    }
+
+    // The user count the database has for a channel, None once it isn't tracked
+    pub async fn tracked_count(&self, channel_id: serenity::ChannelId) -> Option<i32> {
+        busy(storage::tracked_channel(
+            self.pool(),
+            channel_id.get() as i64,
+        ))
+        .await
+        .unwrap()
+        .map(|row| row.user_count)
+    }
+
+    // Stop the reaper the way a SIGTERM would, letting the delete handler drain
+    pub async fn shutdown(&self) {
+        self.voicers.shutdown.cancel();
+        assert!(busy(self.voicers.supervisor.wait(Duration::from_secs(5))).await);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tracks_voice_states_per_channel() {
+        let guild = FakeGuild::new();
+        let first = guild
+            .create_voice_channel(GUILD, new_channel())
+            .await
+            .unwrap();
+        let second = guild
+            .create_voice_channel(GUILD, new_channel())
+            .await
+            .unwrap();
+        let alice = guild.add_member(10, "alice", &[]);
+        let bob = guild.add_member(11, "bob", &[]);
+
+        guild.join(alice, first);
+        guild.join(bob, first);
+        guild.move_member(GUILD, bob, second).await.unwrap();
+
+        assert_eq!(
+            guild.voice_channel_user_count(GUILD, first).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            guild.voice_channel_user_count(GUILD, second).await.unwrap(),
+            1
+        );
+        guild.leave(alice);
+        assert_eq!(
+            guild.voice_channel_user_count(GUILD, first).await.unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn deleting_a_channel_disconnects_everyone_in_it() {
+        let guild = FakeGuild::new();
+        let channel = guild
+            .create_voice_channel(GUILD, new_channel())
+            .await
+            .unwrap();
+        let alice = guild.add_member(10, "alice", &[]);
+        guild.join(alice, channel);
+
+        guild.delete_channel(channel, "test").await.unwrap();
+
+        assert!(!guild.has_channel(channel));
+        assert!(guild.state().voice_states.is_empty());
+        assert!(guild.delete_channel(channel, "test").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn moving_requires_a_voice_connection() {
+        let guild = FakeGuild::new();
+        let channel = guild
+            .create_voice_channel(GUILD, new_channel())
+            .await
+            .unwrap();
+        let alice = guild.add_member(10, "alice", &[]);
+
+        assert!(guild.move_member(GUILD, alice, channel).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn empty_channel_is_deleted_after_the_timeout() {
+        let harness = Harness::new(300).await;
+        let alice = harness.guild.add_member(10, "alice", &[]);
+        let channel = harness.create_vc(alice).await;
+
+        harness.advance(10).await;
+        harness.join(alice, channel).await;
+        assert_eq!(harness.tracked_count(channel).await, Some(1));
+
+        harness.advance(90).await;
+        harness.leave(alice).await;
+        assert_eq!(harness.tracked_count(channel).await, Some(0));
+
+        // Empty since t=100, the pass at t=300 is too early
+        harness.advance(299).await;
+        assert!(harness.guild.has_channel(channel));
+
+        // The pass at t=600 is the first one more than a timeout later
+        harness.advance(201).await;
+        assert!(!harness.guild.has_channel(channel));
+        assert_eq!(harness.tracked_count(channel).await, None);
+    }
+
+    #[tokio::test]
+    async fn occupied_channel_is_kept() {
+        let harness = Harness::new(300).await;
+        let alice = harness.guild.add_member(10, "alice", &[]);
+        let channel = harness.create_vc(alice).await;
+        harness.join(alice, channel).await;
+
+        harness.advance(300 * 10).await;
+
+        assert!(harness.guild.has_channel(channel));
+        assert_eq!(harness.tracked_count(channel).await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn moving_shifts_counts_and_sends_rules() {
+        let harness = Harness::new(300).await;
+        let alice = harness.guild.add_member(10, "alice", &[]);
+        let first = harness.create_vc(alice).await;
+        let second = harness.create_vc(alice).await;
+
+        harness.join(alice, first).await;
+        harness.join(alice, second).await;
+
+        assert_eq!(harness.tracked_count(first).await, Some(0));
+        assert_eq!(harness.tracked_count(second).await, Some(1));
+        let messages = harness.guild.state().messages.clone();
+        let channels: Vec<serenity::ChannelId> =
+            messages.iter().map(|(channel, _)| *channel).collect();
+        assert_eq!(channels, vec![first, second]);
+        assert!(messages.iter().all(|(_, message)| message.contains(RULES)));
+    }
+
+    #[tokio::test]
+    async fn sync_corrects_a_missed_leave() {
+        let harness = Harness::new(300).await;
+        let alice = harness.guild.add_member(10, "alice", &[]);
+        let channel = harness.create_vc(alice).await;
+        harness.join(alice, channel).await;
+        // Gone from Discord without us hearing about it
+        harness.guild.leave(alice);
+
+        // Until the sync on the fourth pass the database still thinks alice is there
+        harness.advance(300 * 4 - 1).await;
+        assert_eq!(harness.tracked_count(channel).await, Some(1));
+
+        // The sync zeroes the count and the same pass reaps it, it's been empty for ages
+        harness.advance(1).await;
+        assert!(!harness.guild.has_channel(channel));
+    }
+
+    #[tokio::test]
+    async fn failed_delete_is_retried_on_the_next_pass() {
+        let harness = Harness::new(300).await;
+        let alice = harness.guild.add_member(10, "alice", &[]);
+        let channel = harness.create_vc(alice).await;
+        harness.guild.state().fail_delete = true;
+
+        harness.advance(600).await;
+        assert!(harness.guild.has_channel(channel));
+        assert_eq!(harness.tracked_count(channel).await, Some(0));
+
+        harness.guild.state().fail_delete = false;
+        harness.advance(300).await;
+        assert!(!harness.guild.has_channel(channel));
+        assert_eq!(harness.tracked_count(channel).await, None);
+    }
+}
@@ -0,0 +1,128 @@
+// Uses the engine only through the public API, the way an embedding bot would
+use poise::serenity_prelude as serenity;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use voicers::config::{Config, Feature};
+use voicers::discord_api::{NewVoiceChannel, VoiceSettings};
+use voicers::engine::{self, Voicers, VoicersData};
+use voicers::health::Health;
+use voicers::testing::{test_pool, FakeGuild, Harness, GUILD};
+use voicers::{lifecycle, storage};
+
+// A host bot with state of its own next to the engine's
+struct HostData {
+    voicers: Voicers,
+    #[allow(dead_code)]
+    greeting: String,
+}
+
+impl VoicersData for HostData {
+    fn voicers(&self) -> &Voicers {
+        &self.voicers
+    }
+}
+
+#[tokio::test]
+async fn tracked_channel_round_trip() {
+    let guild = FakeGuild::new();
+    let pool = test_pool(true).await;
+    let channel = NewVoiceChannel {
+        name: "embedded_vc".to_string(),
+        category: None,
+        permissions: Vec::new(),
+        settings: VoiceSettings::default(),
+        audit_reason: "test".to_string(),
+    };
+
+    let channel_id = lifecycle::create_tracked_channel(&guild, &pool, GUILD, None, channel, 0)
+        .await
+        .unwrap();
+    assert_eq!(
+        storage::tracked_channel_ids(&pool, GUILD.get() as i64)
+            .await
+            .unwrap(),
+        vec![channel_id.get() as i64]
+    );
+
+    assert!(
+        lifecycle::reap_channel(&guild, &pool, channel_id.get() as i64, GUILD.get() as i64)
+            .await
+            .unwrap()
+    );
+    assert!(!guild.has_channel(channel_id));
+    assert!(storage::tracked_channels(&pool).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn harness_is_usable_from_outside_the_crate() {
+    let harness = Harness::new(60).await;
+    let owner = harness.guild.add_member(10, "owner", &[]);
+    let channel: serenity::ChannelId = harness.create_vc(owner).await;
+
+    harness.advance(121).await;
+
+    assert!(!harness.guild.has_channel(channel));
+}
+
+#[tokio::test]
+async fn commands_mount_on_a_host_data_type() {
+    let mut config = Config::default();
+    config.features.disabled_features = vec![Feature::CreateVcPublic.name().to_string()];
+    let config: &'static Config = Box::leak(Box::new(config));
+
+    let data = HostData {
+        voicers: Voicers::new(
+            Arc::new(test_pool(true).await),
+            Arc::new(FakeGuild::new()),
+            config,
+            Arc::new(Health::new()),
+            CancellationToken::new(),
+        ),
+        greeting: "hi".to_string(),
+    };
+    let commands: Vec<poise::Command<HostData, _>> = engine::commands(data.config());
+
+    let names: Vec<&str> = commands
+        .iter()
+        .map(|command| command.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["nametemplate", "vc", "createvc"]);
+    assert!(data.config().is_feature_enabled(Feature::CreateVcPrivate));
+
+    // With both channel types off there's nothing for createvc to do
+    let mut config = Config::default();
+    config.features.disabled_features = vec![
+        Feature::CreateVcPublic.name().to_string(),
+        Feature::CreateVcPrivate.name().to_string(),
+    ];
+    let commands: Vec<poise::Command<HostData, _>> = engine::commands(&config);
+    assert!(!commands.iter().any(|command| command.name == "createvc"));
+
+    // The /vc subcommands go one by one, and /vc with the last of them
+    let mut config = Config::default();
+    config.features.disabled_features = vec![
+        Feature::NameTemplate.name().to_string(),
+        Feature::VcKnock.name().to_string(),
+    ];
+    let commands: Vec<poise::Command<HostData, _>> = engine::commands(&config);
+    assert!(!commands
+        .iter()
+        .any(|command| command.name == "nametemplate"));
+    let vc = commands
+        .iter()
+        .find(|command| command.name == "vc")
+        .unwrap();
+    let subcommands: Vec<&str> = vc
+        .subcommands
+        .iter()
+        .map(|command| command.name.as_str())
+        .collect();
+    assert_eq!(subcommands, vec!["rename", "preset"]);
+
+    config.features.disabled_features.extend([
+        Feature::VcRename.name().to_string(),
+        Feature::VcPresets.name().to_string(),
+    ]);
+    let commands: Vec<poise::Command<HostData, _>> = engine::commands(&config);
+    assert!(!commands.iter().any(|command| command.name == "vc"));
+}